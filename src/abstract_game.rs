@@ -1,7 +1,7 @@
 use super::{
     action_abstraction::{ActionAbstraction},
     card_abstraction::{BucketId, CardAbstraction},
-    game::{Action, GameInfo, GameState, MAX_PLAYERS},
+    game::{Action, GameInfo, GameState, PlayerId},
     node::{Nodes, Node, NodeId},
 };
 
@@ -9,8 +9,21 @@ use poker::Card;
 
 use std::path::Path;
 
+/// Node counts of an abstract game tree
+#[derive(Debug)]
+pub struct TreeStats {
+    /// nodes[r] is the number of nodes in round r, terminal nodes included
+    pub nodes: Vec<usize>,
+    /// Nodes where a player has to act
+    pub decision_nodes: Vec<usize>,
+    /// Decision nodes times the number of buckets of the round
+    pub infosets: Vec<u64>,
+}
+
 pub struct AbstractGame {
     pub game_info: GameInfo,
+    /// Every betting sequence of the action abstraction, frozen once built so it can be shared
+    /// read-only between threads
     pub nodes: Nodes,
     pub action_abstraction: ActionAbstraction,
     pub card_abstraction: CardAbstraction,
@@ -18,51 +31,134 @@ pub struct AbstractGame {
 
 impl AbstractGame {
     pub fn new(game_info: GameInfo, state: GameState, action_abstraction: ActionAbstraction, card_abstraction: CardAbstraction) -> AbstractGame {
-        AbstractGame {
-            game_info,
-            nodes: Nodes::new(state),
-            action_abstraction,
-            card_abstraction,
-        }
+        let nodes = AbstractGame::build_tree(&game_info, state, &action_abstraction);
+        AbstractGame::from_nodes(game_info, nodes, action_abstraction, card_abstraction)
     }
 
     pub fn load_nodes(game_info: GameInfo, path: &Path, action_abstraction: ActionAbstraction, card_abstraction: CardAbstraction) -> AbstractGame {
-        AbstractGame {
+        AbstractGame::from_nodes(game_info, Nodes::from_file(path), action_abstraction, card_abstraction)
+    }
+
+    pub fn from_nodes(game_info: GameInfo, nodes: Nodes, action_abstraction: ActionAbstraction, card_abstraction: CardAbstraction) -> AbstractGame {
+        let abstract_game = AbstractGame {
             game_info,
-            nodes: Nodes::from_file(path),
+            nodes,
             action_abstraction,
             card_abstraction,
+        };
+
+        let stats = abstract_game.tree_stats();
+        for round in 0..stats.nodes.len() {
+            info!("Round {}: {} nodes, {} decision nodes, {} infosets", round, stats.nodes[round], stats.decision_nodes[round], stats.infosets[round]);
+        }
+
+        abstract_game
+    }
+
+    /// Expands every betting sequence reachable from state under the action abstraction
+    pub fn build_tree(game_info: &GameInfo, state: GameState, action_abstraction: &ActionAbstraction) -> Nodes {
+        let mut nodes = Nodes::new(state);
+        let mut stack = vec![nodes.get_root_node_id()];
+
+        while let Some(node_id) = stack.pop() {
+            let state = nodes.get_node(node_id).unwrap().state.clone();
+            if state.is_finished() {
+                continue;
+            }
+
+            for action in action_abstraction.get_actions(game_info, &state) {
+                let child_node_id = nodes.add_node(Node::new(state.apply_action_no_cards(game_info, action).unwrap()));
+                nodes.nodes_map.get_mut(&node_id).unwrap().children.insert(action, child_node_id);
+                stack.push(child_node_id);
+            }
         }
+
+        nodes
+    }
+
+    pub fn tree_stats(&self) -> TreeStats {
+        let num_rounds = self.game_info.num_rounds() as usize;
+        let mut stats = TreeStats {
+            nodes: vec![0; num_rounds],
+            decision_nodes: vec![0; num_rounds],
+            infosets: vec![0; num_rounds],
+        };
+
+        for node in self.nodes.nodes_map.values() {
+            let round = node.state.current_round() as usize;
+            stats.nodes[round] += 1;
+            if !node.state.is_finished() {
+                stats.decision_nodes[round] += 1;
+                stats.infosets[round] += self.card_abstraction.num_buckets(round as u8);
+            }
+        }
+
+        stats
+    }
+
+    pub fn save_nodes(&self, path: &Path) {
+        self.nodes.save(path);
+    }
+
+    pub fn get_root_node_id(&self) -> NodeId {
+        self.nodes.get_root_node_id()
+    }
+
+    pub fn get_state(&self, node_id: NodeId) -> &GameState {
+        &self.nodes.get_node(node_id).unwrap().state
     }
 
     pub fn get_actions(&self, game_state: &GameState) -> Vec<Action> {
         self.action_abstraction.get_actions(&self.game_info, game_state)
     }
 
-    pub fn get_bucket(&self, round:u8, board_cards: &Vec<Card>, hole_cards: &Vec<Card>) -> BucketId {
+    pub fn get_bucket(&self, round:u8, board_cards: &[Card], hole_cards: &[Card]) -> BucketId {
         self.card_abstraction.get_bucket(round, board_cards, hole_cards)
     }
 
-    pub fn apply_action_to_node(&mut self, node_id: NodeId, board_cards_i: &mut usize, action: Action) -> NodeId {
-        //TODO: deal with nolimit situations where actions get rounded or make new action in tree
-        let current_node = self.nodes.get_node(node_id).unwrap();
-        let child = match current_node.children.get(&action) {
-            Some(child_node_id) => {
-                *board_cards_i = self.game_info.total_board_cards(self.nodes.get_node(*child_node_id).unwrap().state.current_round()) as usize;
-                
-                *child_node_id
-            },
-            None => {
-                let new_node = Node::new(current_node.state.apply_action_no_cards(&self.game_info, action).unwrap());
-
-                *board_cards_i = self.game_info.total_board_cards(new_node.state.current_round()) as usize;
-
-                let child_node_id = self.nodes.add_node(new_node);
-                self.nodes.nodes_map.get_mut(&node_id).unwrap().children.insert(action, child_node_id);
-                child_node_id
-            }
-        };
+    /// Bucket of player's hand in a state that tracks cards, with the board dealt so far
+    pub fn get_state_bucket(&self, state: &GameState, player: PlayerId) -> BucketId {
+        self.get_bucket(state.current_round(), state.board_cards(), state.hole_cards(player))
+    }
+
+    /// Node reached by taking action at node_id, None if the action isn't part of the abstraction
+    pub fn get_child(&self, node_id: NodeId, action: Action) -> Option<NodeId> {
+        //TODO: deal with nolimit situations where actions get rounded
+        self.nodes.get_node(node_id)?.children.get(&action).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn abstract_game(name: &str) -> AbstractGame {
+        let config = |suffix: &str| Path::new("game_configs").join(format!("{}{}.json", name, suffix));
+        let game_info = GameInfo::load_game_info(&config(""));
+        let state = GameState::new(&game_info, 0);
+        let action_abstraction = ActionAbstraction::from_config(&config("_action_abstraction"));
+        let card_abstraction = CardAbstraction::from_config(&config("_card_abstraction"));
+        AbstractGame::new(game_info, state, action_abstraction, card_abstraction)
+    }
+
+    #[test]
+    fn kuhn_tree() {
+        let stats = abstract_game("kuhn").tree_stats();
+        // Opening: fold, check or bet. After a check: fold, check or bet. Facing a bet: fold
+        // or call.
+        assert_eq!(stats.nodes, [11]);
+        assert_eq!(stats.decision_nodes, [4]);
+        assert_eq!(stats.infosets, [4 * 3]);
+    }
 
-        child
+    #[test]
+    fn leduc_tree() {
+        let stats = abstract_game("leduc").tree_stats();
+        // A betting round with two raises has 6 decision nodes, 6 folds and 5 ways to end the
+        // round. Each of those starts a second round whose ends are all terminal.
+        assert_eq!(stats.nodes, [6 + 6, 5 * 17]);
+        assert_eq!(stats.decision_nodes, [6, 5 * 6]);
+        // Round one buckets the 6 hole cards, round two the 36 hole and board card pairs
+        assert_eq!(stats.infosets, [6 * 6, 5 * 6 * 36]);
     }
 }
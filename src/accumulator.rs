@@ -0,0 +1,100 @@
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use std::fmt::Debug;
+
+/// Which type regrets and average strategy weights are stored as
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AccumulatorType {
+    F32,
+    F64,
+    /// Pluribus style integer regrets, arithmetic saturates instead of overflowing
+    I32,
+}
+
+/// Value stored in the regret and strategy tables. All arithmetic goes through f64 and the
+/// conversion back decides how precision and overflow are handled.
+pub trait Accumulator: Copy + Default + PartialOrd + Debug + Send + Sync + Serialize + DeserializeOwned + 'static {
+    /// Floor regrets are clamped at when the config doesn't set one
+    const DEFAULT_REGRET_FLOOR: Option<f64> = None;
+
+    fn to_f64(self) -> f64;
+
+    fn from_f64(v: f64) -> Self;
+
+    fn add(self, delta: f64) -> Self {
+        Self::from_f64(self.to_f64() + delta)
+    }
+
+    fn scale(self, d: f64) -> Self {
+        Self::from_f64(self.to_f64() * d)
+    }
+}
+
+impl Accumulator for f32 {
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(v: f64) -> Self {
+        v as f32
+    }
+}
+
+impl Accumulator for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_f64(v: f64) -> Self {
+        v
+    }
+}
+
+impl Accumulator for i32 {
+    /// Pluribus's floor, keeps regrets far enough from i32::MIN that they can recover
+    const DEFAULT_REGRET_FLOOR: Option<f64> = Some(-310000000.);
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    /// Rounds to the nearest integer, float to int casts saturate at i32::MIN/i32::MAX
+    fn from_f64(v: f64) -> Self {
+        v.round() as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn i32_saturates_instead_of_wrapping() {
+        assert_eq!(i32::MAX.add(1.), i32::MAX);
+        assert_eq!((i32::MAX - 5).add(1e12), i32::MAX);
+        assert_eq!(i32::MIN.add(-1.), i32::MIN);
+        assert_eq!((i32::MIN + 5).add(-1e12), i32::MIN);
+        // Going through f64 means the sum can't overflow before it is clamped
+        assert_eq!(i32::MAX.add(i32::MAX as f64), i32::MAX);
+    }
+
+    #[test]
+    fn i32_rounds_to_the_nearest_integer() {
+        assert_eq!(10.add(0.4), 10);
+        assert_eq!(10.add(0.6), 11);
+        assert_eq!((-10).add(-0.6), -11);
+        assert_eq!(0.add(2.5), 3);
+    }
+
+    #[test]
+    fn i32_discount_does_not_wrap() {
+        // The LCFR discount t/(t + 1) of the first few discount intervals
+        for d in [0.5, 2. / 3., 0.75, 0.999] {
+            assert_eq!(i32::MAX.scale(d), (i32::MAX as f64 * d).round() as i32);
+            assert_eq!(i32::MIN.scale(d), (i32::MIN as f64 * d).round() as i32);
+            assert!(i32::MAX.scale(d) > 0 && i32::MIN.scale(d) < 0);
+        }
+        assert_eq!(2_000_000_001.scale(0.5), 1_000_000_001);
+        assert_eq!((-2_000_000_001).scale(0.5), -1_000_000_001);
+    }
+}
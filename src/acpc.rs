@@ -0,0 +1,445 @@
+/*
+* Reading and writing game definitions in the ACPC .game format, see
+* http://www.computerpokercompetition.org/downloads/documents/protocols/protocol.pdf
+*/
+
+use super::{
+    game::{Action, BettingType, GameInfo, GameState, PlayerId, MAX_PLAYERS},
+    hand_ranking::HandRanking,
+};
+
+use poker::Card;
+
+use std::fmt::Write;
+
+/// Stack ACPC gives players when a game doesn't set one
+const DEFAULT_STACK: u32 = i32::MAX as u32;
+/// Max raises ACPC uses when a game doesn't set them
+const DEFAULT_MAX_RAISES: u8 = u8::MAX;
+
+/// Parses the GAMEDEF block of an ACPC game definition. Keys are case insensitive, lines after
+/// a # are ignored and player numbers are 1 indexed like in the ACPC code.
+pub fn parse_game_def(s: &str) -> Result<GameInfo, &'static str> {
+    let mut lines = s.lines()
+        .map(|l| l.split('#').next().unwrap().trim())
+        .filter(|l| !l.is_empty());
+    if !lines.next().is_some_and(|l| l.eq_ignore_ascii_case("gamedef")) {
+        return Err("game definition doesn't start with GAMEDEF");
+    }
+
+    let mut betting_type = None;
+    let mut num_players = None;
+    let mut num_rounds = None;
+    let mut starting_stacks = None;
+    let mut blinds = None;
+    let mut raise_sizes = None;
+    let mut first_player = None;
+    let mut max_raises = None;
+    let mut num_suits = None;
+    let mut num_ranks = None;
+    let mut num_hole_cards = None;
+    let mut num_board_cards = None;
+    let mut finished = false;
+
+    for line in lines {
+        if line.eq_ignore_ascii_case("end gamedef") {
+            finished = true;
+            break;
+        }
+        if line.eq_ignore_ascii_case("limit") {
+            betting_type = Some(BettingType::Limit);
+            continue;
+        }
+        if line.eq_ignore_ascii_case("nolimit") {
+            betting_type = Some(BettingType::NoLimit);
+            continue;
+        }
+
+        let (key, values) = line.split_once('=').ok_or("expected key = values")?;
+        let values: Vec<u32> = values.split_whitespace()
+            .map(|v| v.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| "values have to be non negative integers")?;
+        let single = || match values[..] {
+            [v] => u8::try_from(v).map_err(|_| "value is too large"),
+            _ => Err("expected a single value"),
+        };
+        let small = || values.iter().map(|&v| u8::try_from(v).map_err(|_| "value is too large")).collect::<Result<Vec<u8>, _>>();
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "numplayers" => num_players = Some(single()?),
+            "numrounds" => num_rounds = Some(single()?),
+            "stack" => starting_stacks = Some(values),
+            "blind" => blinds = Some(values),
+            "raisesize" => raise_sizes = Some(values),
+            "firstplayer" => first_player = Some(small()?
+                .into_iter()
+                .map(|p| p.checked_sub(1).ok_or("first players are numbered from 1"))
+                .collect::<Result<Vec<PlayerId>, _>>()?),
+            "maxraises" => max_raises = Some(small()?),
+            "numsuits" => num_suits = Some(single()?),
+            "numranks" => num_ranks = Some(single()?),
+            "numholecards" => num_hole_cards = Some(single()?),
+            "numboardcards" => num_board_cards = Some(small()?),
+            _ => return Err("unknown game definition key"),
+        }
+    }
+    if !finished {
+        return Err("game definition doesn't end with END GAMEDEF");
+    }
+
+    let betting_type = betting_type.ok_or("game definition needs limit or nolimit")?;
+    let num_players = num_players.ok_or("game definition needs numPlayers")?;
+    let num_rounds = num_rounds.ok_or("game definition needs numRounds")?;
+    let game_info = GameInfo {
+        starting_stacks: starting_stacks.unwrap_or_else(|| vec![DEFAULT_STACK; num_players as usize]),
+        blinds: blinds.ok_or("game definition needs blind")?,
+        raise_sizes: match betting_type {
+            BettingType::Limit => raise_sizes.ok_or("limit games need raiseSize")?,
+            BettingType::NoLimit => raise_sizes.unwrap_or_else(|| vec![0; num_rounds as usize]),
+        },
+        betting_type,
+        num_players,
+        num_rounds,
+        max_raises: max_raises.unwrap_or_else(|| vec![DEFAULT_MAX_RAISES; num_rounds as usize]),
+        first_player: first_player.unwrap_or_else(|| vec![0; num_rounds as usize]),
+        num_suits: num_suits.ok_or("game definition needs numSuits")?,
+        num_ranks: num_ranks.ok_or("game definition needs numRanks")?,
+        lowest_rank: 0,
+        num_hole_cards: num_hole_cards.ok_or("game definition needs numHoleCards")?,
+        num_board_cards: num_board_cards.ok_or("game definition needs numBoardCards")?,
+        hand_ranking: HandRanking::Standard,
+        ante: 0,
+        big_blind_ante: 0,
+        straddle: 0,
+        rotate_button: false,
+    };
+    game_info.validate()?;
+
+    Ok(game_info)
+}
+
+/// Writes game_info as an ACPC game definition that parse_game_def reads back. ACPC games have
+/// no antes, straddles, moving button or short decks, so games with them are an error.
+pub fn game_def_string(game_info: &GameInfo) -> Result<String, &'static str> {
+    fn join<T: ToString>(values: &[T]) -> String {
+        values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+    }
+
+    if game_info.ante > 0 || game_info.big_blind_ante > 0 {
+        return Err("ACPC games have no antes");
+    }
+    if game_info.straddle > 0 {
+        return Err("ACPC games have no straddle");
+    }
+    if game_info.rotate_button {
+        return Err("ACPC games have no moving button");
+    }
+    if game_info.lowest_rank != 0 {
+        return Err("ACPC decks start at the twos");
+    }
+
+    let mut s = String::from("GAMEDEF\n");
+    match game_info.betting_type {
+        BettingType::Limit => s.push_str("limit\n"),
+        BettingType::NoLimit => s.push_str("nolimit\n"),
+    }
+    writeln!(s, "numPlayers = {}", game_info.num_players).unwrap();
+    writeln!(s, "numRounds = {}", game_info.num_rounds).unwrap();
+    if game_info.starting_stacks.iter().any(|&stack| stack != DEFAULT_STACK) {
+        writeln!(s, "stack = {}", join(&game_info.starting_stacks)).unwrap();
+    }
+    writeln!(s, "blind = {}", join(&game_info.blinds)).unwrap();
+    if let BettingType::Limit = game_info.betting_type {
+        writeln!(s, "raiseSize = {}", join(&game_info.raise_sizes)).unwrap();
+    }
+    writeln!(s, "firstPlayer = {}", join(&game_info.first_player.iter().map(|p| p + 1).collect::<Vec<_>>())).unwrap();
+    if game_info.max_raises.iter().any(|&m| m != DEFAULT_MAX_RAISES) {
+        writeln!(s, "maxRaises = {}", join(&game_info.max_raises)).unwrap();
+    }
+    writeln!(s, "numSuits = {}", game_info.num_suits).unwrap();
+    writeln!(s, "numRanks = {}", game_info.num_ranks).unwrap();
+    writeln!(s, "numHoleCards = {}", game_info.num_hole_cards).unwrap();
+    writeln!(s, "numBoardCards = {}", join(&game_info.num_board_cards)).unwrap();
+    s.push_str("END GAMEDEF\n");
+    Ok(s)
+}
+
+/// A MATCHSTATE message from an ACPC dealer
+#[derive(Debug)]
+pub struct MatchState {
+    /// Seat of the player receiving the message in this hand
+    pub position: PlayerId,
+    pub hand_number: u32,
+    /// Every action of the hand so far, in order across rounds
+    pub actions: Vec<Action>,
+    /// Hole cards per player, empty for the ones not shown
+    pub hole_cards: [Vec<Card>; MAX_PLAYERS],
+    pub board_cards: Vec<Card>,
+}
+
+impl MatchState {
+    /// State the actions lead to from the start of the hand
+    pub fn state(&self, game_info: &GameInfo) -> Result<GameState, &'static str> {
+        let mut state = GameState::new(game_info, self.hand_number);
+        for &action in &self.actions {
+            state = state.apply_action_no_cards(game_info, action)?;
+        }
+        Ok(state)
+    }
+}
+
+/// Parses "MATCHSTATE:position:hand:betting:cards" where betting has the actions of each round
+/// separated by / and cards has the hole cards of each player separated by | followed by the
+/// board cards of each round separated by /
+pub fn parse_match_state(game_info: &GameInfo, line: &str) -> Result<MatchState, &'static str> {
+    let mut parts = line.trim_end().splitn(5, ':');
+    if parts.next() != Some("MATCHSTATE") {
+        return Err("message isn't a MATCHSTATE");
+    }
+    let position: PlayerId = parts.next().ok_or("MATCHSTATE is missing the position")?
+        .parse().map_err(|_| "invalid position")?;
+    let hand_number: u32 = parts.next().ok_or("MATCHSTATE is missing the hand number")?
+        .parse().map_err(|_| "invalid hand number")?;
+    let betting = parts.next().ok_or("MATCHSTATE is missing the betting")?;
+    let cards = parts.next().ok_or("MATCHSTATE is missing the cards")?;
+    if position >= game_info.num_players {
+        return Err("position is out of range");
+    }
+
+    let mut actions = Vec::new();
+    for (round, round_betting) in betting.split('/').enumerate() {
+        let round = u8::try_from(round).map_err(|_| "too many rounds in betting")?;
+        // Every action starts with a letter, raises may be followed by their amount
+        let mut start = 0;
+        for (i, c) in round_betting.char_indices().skip(1) {
+            if !c.is_ascii_digit() {
+                actions.push(parse_action(game_info, round, &round_betting[start..i])?);
+                start = i;
+            }
+        }
+        if !round_betting.is_empty() {
+            actions.push(parse_action(game_info, round, &round_betting[start..])?);
+        }
+    }
+
+    let mut rounds = cards.split('/');
+    let mut hole_cards = [(); MAX_PLAYERS].map(|_| Vec::new());
+    for (player, player_cards) in rounds.next().unwrap_or("").split('|').enumerate() {
+        if player >= game_info.num_players as usize {
+            return Err("too many players in cards");
+        }
+        hole_cards[player] = parse_cards(player_cards)?;
+    }
+    let mut board_cards = Vec::new();
+    for round_cards in rounds {
+        board_cards.extend(parse_cards(round_cards)?);
+    }
+
+    Ok(MatchState { position, hand_number, actions, hole_cards, board_cards })
+}
+
+/// Parses a single action like "f", "c" or "r250" made in round. Limit raises don't need an
+/// amount, no limit raises are to the total amount given.
+pub fn parse_action(game_info: &GameInfo, round: u8, s: &str) -> Result<Action, &'static str> {
+    let mut chars = s.chars();
+    let action = match chars.next() {
+        Some('f') => Action::Fold,
+        Some('c') | Some('k') => Action::Call,
+        Some('r') | Some('b') => match game_info.betting_type {
+            BettingType::Limit => Action::Raise(*game_info.raise_sizes.get(round as usize).ok_or("round is out of range")?),
+            BettingType::NoLimit => Action::Raise(chars.as_str().parse().map_err(|_| "no limit raise is missing its amount")?),
+        },
+        _ => return Err("invalid action"),
+    };
+    match action {
+        Action::Raise(_) => Ok(action),
+        _ if chars.as_str().is_empty() => Ok(action),
+        _ => Err("only raises take an amount"),
+    }
+}
+
+/// Betting part of a MATCHSTATE from the actions of each round so far
+pub fn format_betting(game_info: &GameInfo, round_actions: &[Vec<Action>]) -> String {
+    round_actions.iter()
+        .map(|actions| actions.iter().map(|&a| format_action(game_info, a)).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// MATCHSTATE message for position once round has been reached. hole_cards holds what position
+/// gets to see, empty for hidden hands, and board_cards at least the board up to round.
+pub fn format_match_state(game_info: &GameInfo, position: PlayerId, hand_number: u32, betting: &str, hole_cards: &[Vec<Card>], round: u8, board_cards: &[Card]) -> String {
+    format!("MATCHSTATE:{}:{}:{}:{}", position, hand_number, betting, format_hand_cards(game_info, hole_cards, round, board_cards))
+}
+
+/// Hole cards of each player separated by | followed by the board cards of every round up to
+/// round, each after a /
+pub fn format_hand_cards(game_info: &GameInfo, hole_cards: &[Vec<Card>], round: u8, board_cards: &[Card]) -> String {
+    let mut cards = hole_cards.iter()
+        .take(game_info.num_players as usize)
+        .map(|h| format_cards(h))
+        .collect::<Vec<_>>()
+        .join("|");
+    for r in 1..=round {
+        let start = game_info.total_board_cards(r - 1) as usize;
+        let end = game_info.total_board_cards(r) as usize;
+        cards.push('/');
+        cards.push_str(&format_cards(&board_cards[start..end]));
+    }
+    cards
+}
+
+/// Parses cards written back to back like "Ah9s"
+pub fn parse_cards(s: &str) -> Result<Vec<Card>, &'static str> {
+    if !s.len().is_multiple_of(2) {
+        return Err("cards have to be two characters each");
+    }
+    (0..s.len()).step_by(2)
+        .map(|i| s.get(i..i + 2).ok_or("invalid card")?.parse().map_err(|_| "invalid card"))
+        .collect()
+}
+
+/// Writes cards the way parse_cards reads them
+pub fn format_cards(cards: &[Card]) -> String {
+    cards.iter().map(|c| format!("{}{}", c.rank().as_char(), c.suit().as_char())).collect()
+}
+
+/// ACPC form of an action, raises carry the amount raised to in no limit games
+pub fn format_action(game_info: &GameInfo, action: Action) -> String {
+    match (action, &game_info.betting_type) {
+        (Action::Fold, _) => "f".to_string(),
+        (Action::Call, _) => "c".to_string(),
+        (Action::Raise(_), BettingType::Limit) => "r".to_string(),
+        (Action::Raise(r), BettingType::NoLimit) => format!("r{}", r),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs, path::Path};
+
+    /// holdem.limit.2p.reverse_blinds.game from the ACPC server code, which leaves out stack
+    const REVERSE_BLINDS_LIMIT: &str = "\
+GAMEDEF
+limit
+numPlayers = 2
+numRounds = 4
+blind = 5 10
+raiseSize = 10 10 20 20
+firstPlayer = 1 2 2 2
+maxRaises = 3 4 4 4
+numSuits = 4
+numRanks = 13
+numHoleCards = 2
+numBoardCards = 0 3 1 1
+END GAMEDEF
+";
+
+    fn load(name: &str) -> GameInfo {
+        GameInfo::load_game_info(&Path::new("game_configs").join(name))
+    }
+
+    #[test]
+    fn checked_in_game_files_match_their_json() {
+        for name in ["kuhn", "leduc", "holdem", "holdem_nl"] {
+            let contents = fs::read_to_string(format!("game_configs/{}.game", name)).unwrap();
+            let game_info = parse_game_def(&contents).unwrap();
+            assert_eq!(game_info, load(&format!("{}.json", name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn parses_games_without_stacks() {
+        let game_info = parse_game_def(REVERSE_BLINDS_LIMIT).unwrap();
+        assert_eq!(game_info.betting_type, BettingType::Limit);
+        assert_eq!(game_info.starting_stacks, vec![DEFAULT_STACK; 2]);
+        assert_eq!(game_info.blinds, vec![5, 10]);
+        assert_eq!(game_info.raise_sizes, vec![10, 10, 20, 20]);
+        assert_eq!(game_info.first_player, vec![0, 1, 1, 1]);
+        assert_eq!(game_info.max_raises, vec![3, 4, 4, 4]);
+        assert_eq!(game_info.num_board_cards, vec![0, 3, 1, 1]);
+    }
+
+    #[test]
+    fn game_def_string_round_trips() {
+        let mut games: Vec<GameInfo> = ["kuhn.json", "leduc.json", "holdem.json", "holdem_nl.json"]
+            .into_iter()
+            .map(load)
+            .collect();
+        games.push(parse_game_def(REVERSE_BLINDS_LIMIT).unwrap());
+        for game_info in games {
+            assert_eq!(parse_game_def(&game_def_string(&game_info).unwrap()).unwrap(), game_info);
+        }
+    }
+
+    #[test]
+    fn game_def_string_rejects_what_acpc_games_lack() {
+        assert!(game_def_string(&load("holdem_nl_6max.json")).is_err());
+        assert!(game_def_string(&load("holdem_nl_short_deck.json")).is_err());
+
+        let mut game_info = load("holdem_nl.json");
+        game_info.ante = 10;
+        assert!(game_def_string(&game_info).is_err());
+        game_info.ante = 0;
+        game_info.straddle = 200;
+        assert!(game_def_string(&game_info).is_err());
+        game_info.straddle = 0;
+        game_info.rotate_button = true;
+        assert!(game_def_string(&game_info).is_err());
+    }
+
+    #[test]
+    fn match_states_round_trip() {
+        let game_info = load("holdem_nl.json");
+        let line = "MATCHSTATE:1:42:r300c/cr600c/:|Kd7c/As8h2d/Tc";
+        let match_state = parse_match_state(&game_info, line).unwrap();
+        assert_eq!(match_state.position, 1);
+        assert_eq!(match_state.hand_number, 42);
+        assert_eq!(match_state.actions, [Action::Raise(300), Action::Call, Action::Call, Action::Raise(600), Action::Call]);
+        assert!(match_state.hole_cards[0].is_empty());
+        assert_eq!(match_state.hole_cards[1], parse_cards("Kd7c").unwrap());
+        assert_eq!(match_state.board_cards, parse_cards("As8h2dTc").unwrap());
+
+        let betting = format_betting(&game_info, &[
+            vec![Action::Raise(300), Action::Call],
+            vec![Action::Call, Action::Raise(600), Action::Call],
+            vec![],
+        ]);
+        assert_eq!(format_match_state(&game_info, 1, 42, &betting, &match_state.hole_cards, 2, &match_state.board_cards), line);
+    }
+
+    #[test]
+    fn limit_raises_take_the_round_size() {
+        let game_info = load("leduc.json");
+        let line = "MATCHSTATE:0:7:rrc/r:3h|4c/2c";
+        let match_state = parse_match_state(&game_info, line).unwrap();
+        assert_eq!(match_state.actions, [Action::Raise(2), Action::Raise(2), Action::Call, Action::Raise(4)]);
+        assert_eq!(match_state.state(&game_info).unwrap().max_spent(), 9);
+
+        let betting = format_betting(&game_info, &[match_state.actions[..3].to_vec(), match_state.actions[3..].to_vec()]);
+        assert_eq!(format_match_state(&game_info, 0, 7, &betting, &match_state.hole_cards, 1, &match_state.board_cards), line);
+    }
+
+    #[test]
+    fn rejects_broken_match_states() {
+        let game_info = load("holdem_nl.json");
+        assert!(parse_match_state(&game_info, "MATCHSTATE:0:0:r:AsAh|").is_err());
+        assert!(parse_match_state(&game_info, "MATCHSTATE:2:0::AsAh|").is_err());
+        assert!(parse_match_state(&game_info, "MATCHSTATE:0:0:c12:AsAh|").is_err());
+        assert!(parse_match_state(&game_info, "MATCHSTATE:0:0::AsA|").is_err());
+        assert!(parse_match_state(&game_info, "MATCH:0:0::AsAh|").is_err());
+    }
+
+    #[test]
+    fn rejects_broken_game_defs() {
+        assert!(parse_game_def("limit\nEND GAMEDEF").is_err());
+        assert!(parse_game_def(REVERSE_BLINDS_LIMIT.trim_end_matches("END GAMEDEF\n")).is_err());
+        assert!(parse_game_def(&REVERSE_BLINDS_LIMIT.replace("numSuits", "numColours")).is_err());
+        assert!(parse_game_def(&REVERSE_BLINDS_LIMIT.replace("firstPlayer = 1", "firstPlayer = 0")).is_err());
+        // Limit games can't do without raise sizes
+        assert!(parse_game_def(&REVERSE_BLINDS_LIMIT.replace("raiseSize = 10 10 20 20\n", "")).is_err());
+    }
+}
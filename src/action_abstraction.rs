@@ -1,5 +1,5 @@
 use super::{
-    game::{Action, GameInfo, GameState},
+    game::{Action, GameInfo, GameState, PlayerId},
 };
 
 use std::{fs, path::Path};
@@ -10,7 +10,14 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Deserialize, Serialize)]
 pub enum AbstractRaiseType {
     AllIn,
+    /// Raise by this fraction of the pot after calling, 1 is a pot sized raise
     PotRatio(f32),
+    /// Put in this fraction of the chips left in the stack
+    StackRatio(f32),
+    /// Raise by this multiple of the last raise of the round, or the big blind if there was none
+    LastRaiseMultiple(f32),
+    /// Smallest legal raise
+    MinRaise,
     /// Usually just an option for limit games
     Fixed(u32),
 }
@@ -24,10 +31,68 @@ pub enum RaiseRoundConfig {
     Before(u32),
 }
 
+/// Spots a raise is limited to on top of its round config, unset fields don't restrict anything
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RaiseConditions {
+    /// Effective stack to pot ratio has to be at least this
+    pub min_spr: Option<f32>,
+    /// Effective stack to pot ratio has to be below this
+    pub max_spr: Option<f32>,
+    /// Bounds on the players that haven't folded or gone all in, acting player included
+    pub min_active_players: Option<u8>,
+    pub max_active_players: Option<u8>,
+    /// Players allowed to make the raise
+    pub positions: Option<Vec<PlayerId>>,
+    /// Only when facing a bet if true, only when opening(nothing to call) if false
+    pub facing_bet: Option<bool>,
+}
+
+impl RaiseConditions {
+    pub fn allows(&self, game_info: &GameInfo, game_state: &GameState) -> bool {
+        let player = match game_state.current_player() {
+            Ok(p) => p,
+            Err(_) => return false,
+        };
+
+        if self.min_spr.is_some() || self.max_spr.is_some() {
+            let spr = effective_stack(game_info, game_state, player) as f32 / game_state.pot_total(game_info).max(1) as f32;
+            if self.min_spr.is_some_and(|min| spr < min) || self.max_spr.is_some_and(|max| spr >= max) {
+                return false;
+            }
+        }
+
+        let active_players = game_state.num_active_players(game_info);
+        if self.min_active_players.is_some_and(|min| active_players < min) || self.max_active_players.is_some_and(|max| active_players > max) {
+            return false;
+        }
+
+        if self.positions.as_ref().is_some_and(|positions| !positions.contains(&player)) {
+            return false;
+        }
+
+        let facing_bet = game_state.max_spent() > game_state.player_spent(player);
+        self.facing_bet.is_none_or(|f| f == facing_bet)
+    }
+}
+
+/// Chips player can still win or lose against the deepest opponent still in the hand
+fn effective_stack(game_info: &GameInfo, game_state: &GameState, player: PlayerId) -> u32 {
+    let remaining = |p: PlayerId| game_state.player_stack(p) - game_state.player_spent(p);
+    let deepest_opponent = (0..game_info.num_players())
+        .filter(|&p| p != player && !game_state.has_folded(p))
+        .map(remaining)
+        .max()
+        .unwrap_or(0);
+    remaining(player).min(deepest_opponent)
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct AbstractRaise {
     pub raise_type: AbstractRaiseType,
     pub round_config: Vec<RaiseRoundConfig>,
+    #[serde(default)]
+    pub conditions: RaiseConditions,
 }
 
 /// Used to generate possible abstract actions for a given state
@@ -68,3 +133,107 @@ impl ActionAbstraction {
         actions
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holdem_nl() -> GameInfo {
+        GameInfo::load_game_info(Path::new("game_configs/holdem_nl.json"))
+    }
+
+    /// The small blind limps, leaving the big blind to act with nothing to call into a 200 pot
+    fn limped(game_info: &GameInfo) -> GameState {
+        GameState::new(game_info, 0).apply_action_no_cards(game_info, Action::Call).unwrap()
+    }
+
+    #[test]
+    fn spr_bounds_use_the_effective_stack() {
+        let game_info = holdem_nl();
+        // 19900 behind each into a 200 pot
+        let state = limped(&game_info);
+        let spr = |min_spr, max_spr| RaiseConditions { min_spr, max_spr, ..Default::default() }.allows(&game_info, &state);
+        assert!(spr(Some(99.5), None));
+        assert!(!spr(Some(99.6), None));
+        assert!(spr(None, Some(99.6)));
+        assert!(!spr(None, Some(99.5)));
+        assert!(spr(Some(99.), Some(100.)));
+
+        // The short stack has 5000 behind, which is all the big blind can win
+        let mut game_info = holdem_nl();
+        game_info.starting_stacks = vec![20000, 5100];
+        let state = limped(&game_info);
+        let conditions = RaiseConditions { max_spr: Some(25.), ..Default::default() };
+        assert!(!conditions.allows(&game_info, &state));
+        let conditions = RaiseConditions { max_spr: Some(25.1), ..Default::default() };
+        assert!(conditions.allows(&game_info, &state));
+    }
+
+    #[test]
+    fn active_player_bounds_skip_folded_and_all_in_players() {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/holdem_nl_6max.json"));
+        let active = |state: &GameState, min_active_players, max_active_players| {
+            RaiseConditions { min_active_players, max_active_players, ..Default::default() }.allows(&game_info, state)
+        };
+        let state = GameState::new(&game_info, 0);
+        assert!(active(&state, Some(6), Some(6)));
+        assert!(!active(&state, Some(7), None));
+        assert!(!active(&state, None, Some(5)));
+
+        let state = state.apply_action_no_cards(&game_info, Action::Fold).unwrap();
+        assert!(active(&state, None, Some(5)));
+        assert!(!active(&state, Some(6), None));
+
+        // After an all in only the player facing it can still act
+        let game_info = holdem_nl();
+        let state = GameState::new(&game_info, 0).apply_action_no_cards(&game_info, Action::Raise(20000)).unwrap();
+        let conditions = RaiseConditions { min_active_players: Some(2), ..Default::default() };
+        assert!(!conditions.allows(&game_info, &state));
+        let conditions = RaiseConditions { max_active_players: Some(1), ..Default::default() };
+        assert!(conditions.allows(&game_info, &state));
+    }
+
+    #[test]
+    fn positions_limit_the_acting_player() {
+        let game_info = holdem_nl();
+        let positions = |state: &GameState, positions: Vec<PlayerId>| {
+            RaiseConditions { positions: Some(positions), ..Default::default() }.allows(&game_info, state)
+        };
+        // The small blind acts first, then the big blind
+        let state = GameState::new(&game_info, 0);
+        assert!(positions(&state, vec![1]));
+        assert!(!positions(&state, vec![0]));
+        let state = limped(&game_info);
+        assert!(positions(&state, vec![0]));
+        assert!(positions(&state, vec![1, 0]));
+        assert!(!positions(&state, vec![1]));
+        assert!(!positions(&state, vec![]));
+    }
+
+    #[test]
+    fn facing_bet_splits_opening_from_raising() {
+        let game_info = holdem_nl();
+        let facing_bet = |state: &GameState, facing_bet| {
+            RaiseConditions { facing_bet: Some(facing_bet), ..Default::default() }.allows(&game_info, state)
+        };
+        // The big blind checking its option is opening
+        let state = limped(&game_info);
+        assert!(facing_bet(&state, false));
+        assert!(!facing_bet(&state, true));
+        // The small blind facing a raise
+        let state = state.apply_action_no_cards(&game_info, Action::Raise(300)).unwrap();
+        assert!(facing_bet(&state, true));
+        assert!(!facing_bet(&state, false));
+        // The small blind still owes the rest of the big blind preflop
+        let state = GameState::new(&game_info, 0);
+        assert!(facing_bet(&state, true));
+    }
+
+    #[test]
+    fn nothing_is_allowed_once_the_hand_is_over() {
+        let game_info = holdem_nl();
+        let state = GameState::new(&game_info, 0).apply_action_no_cards(&game_info, Action::Fold).unwrap();
+        assert!(!RaiseConditions::default().allows(&game_info, &state));
+        assert!(RaiseConditions::default().allows(&game_info, &limped(&game_info)));
+    }
+}
@@ -0,0 +1,218 @@
+/*
+* Maps no limit raises the action abstraction doesn't contain onto abstract raises, and the
+* abstract actions of a strategy back onto real actions, so a bot can follow its abstract game
+* while the real game state is tracked separately for legality and payouts.
+*/
+
+use super::{
+    abstract_game::AbstractGame,
+    game::{Action, BettingType, GameInfo, GameState},
+    node::NodeId,
+};
+
+use serde::{Deserialize, Serialize};
+
+use rand::prelude::*;
+
+/// How a real raise between two abstract raises picks one of them
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, clap::ValueEnum)]
+pub enum TranslationMethod {
+    /// Closest abstract raise by pot fraction
+    Nearest,
+    /// Abstract raise the pseudo-harmonic mapping gives more probability to
+    #[default]
+    PseudoHarmonic,
+    /// Samples the abstract raise with the pseudo-harmonic mapping's probabilities
+    RandomizedPseudoHarmonic,
+}
+
+/// Size of a raise to raise_to as a fraction of the pot after the current player calls
+pub fn pot_fraction(game_info: &GameInfo, state: &GameState, raise_to: u32) -> f64 {
+    let player = state.current_player().expect("state has no player to act");
+    let to_call = state.max_spent().saturating_sub(state.player_spent(player));
+    let pot = (state.pot_total(game_info) + to_call).max(1);
+    raise_to.saturating_sub(state.max_spent()) as f64 / pot as f64
+}
+
+/// Probability the pseudo-harmonic mapping of Ganzfried and Sandholm maps a bet of pot fraction
+/// x onto the smaller bet a rather than the larger bet b, a <= x <= b
+pub fn pseudo_harmonic(a: f64, b: f64, x: f64) -> f64 {
+    if b <= a {
+        return 1.;
+    }
+    ((b - x) * (1. + a) / ((b - a) * (1. + x))).clamp(0., 1.)
+}
+
+/// Maps the real raise to raise_to in real_state onto one of the raises in abstract_actions,
+/// the actions of the abstract state the bot is at. Returns None if there are no abstract raises.
+pub fn translate_raise<R: Rng>(method: TranslationMethod, game_info: &GameInfo, real_state: &GameState, raise_to: u32, abstract_state: &GameState, abstract_actions: &[Action], rng: &mut R) -> Option<Action> {
+    let x = pot_fraction(game_info, real_state, raise_to);
+    let mut raises: Vec<(f64, Action)> = abstract_actions.iter()
+        .filter_map(|&a| match a {
+            Action::Raise(r) => Some((pot_fraction(game_info, abstract_state, r), a)),
+            _ => None,
+        })
+        .collect();
+    raises.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let above = raises.partition_point(|&(f, _)| f < x);
+    let (a, b) = match (above.checked_sub(1).map(|i| raises[i]), raises.get(above).copied()) {
+        (Some(a), Some(b)) => (a, b),
+        // Bets outside the abstraction's range go to its smallest or largest raise
+        (Some(a), None) => return Some(a.1),
+        (None, Some(b)) => return Some(b.1),
+        (None, None) => return None,
+    };
+
+    let p_a = pseudo_harmonic(a.0, b.0, x);
+    let smaller = match method {
+        TranslationMethod::Nearest => x - a.0 <= b.0 - x,
+        TranslationMethod::PseudoHarmonic => p_a >= 0.5,
+        TranslationMethod::RandomizedPseudoHarmonic => rng.gen_bool(p_a),
+    };
+
+    Some(if smaller { a.1 } else { b.1 })
+}
+
+/// Real action for an abstract action chosen at abstract_state. Raises keep their pot fraction
+/// and get clamped to the real raise range, all-ins stay all-ins, and a raise that isn't possible
+/// in the real game becomes a call.
+pub fn abstract_to_real(game_info: &GameInfo, real_state: &GameState, abstract_state: &GameState, action: Action) -> Action {
+    let r = match action {
+        Action::Raise(r) => r,
+        _ => return action,
+    };
+    if !matches!(game_info.betting_type(), BettingType::NoLimit) {
+        return if real_state.is_valid_action(game_info, action) { action } else { Action::Call };
+    }
+
+    let (min_raise, max_raise) = real_state.raise_range(game_info);
+    if max_raise == 0 {
+        return Action::Call;
+    }
+    let player = abstract_state.current_player().expect("state has no player to act");
+    if r >= abstract_state.player_stack(player) {
+        return Action::Raise(max_raise);
+    }
+
+    let fraction = pot_fraction(game_info, abstract_state, r);
+    let real_player = real_state.current_player().expect("state has no player to act");
+    let to_call = real_state.max_spent().saturating_sub(real_state.player_spent(real_player));
+    let pot = real_state.pot_total(game_info) + to_call;
+    let raise_to = real_state.max_spent() + (fraction * pot as f64).round() as u32;
+
+    Action::Raise(raise_to.clamp(min_raise, max_raise))
+}
+
+/// Returns if abstract_state is at the same decision as real_state. The abstract game is built
+/// with the button in seat 0, so players are compared by their position relative to the button.
+pub fn same_decision(game_info: &GameInfo, real_state: &GameState, abstract_state: &GameState) -> bool {
+    !abstract_state.is_finished()
+        && abstract_state.current_round() == real_state.current_round()
+        && abstract_state.current_player() == real_state.current_player().map(|p| game_info.position(real_state.button(), p))
+}
+
+/// Follows real actions from the start of hand hand_id through the abstract game, translating
+/// raises it doesn't contain. Returns the real state the actions lead to and the abstract node,
+/// or None for the node once an action couldn't be followed or the abstract game got out of step.
+pub fn follow_actions<R: Rng>(method: TranslationMethod, abstract_game: &AbstractGame, hand_id: u32, actions: &[Action], rng: &mut R) -> Result<(GameState, Option<NodeId>), &'static str> {
+    let game_info = &abstract_game.game_info;
+    let mut node_id = Some(abstract_game.get_root_node_id());
+    let mut state = GameState::new(game_info, hand_id);
+
+    for &action in actions {
+        node_id = node_id.and_then(|node_id| {
+            let abstract_state = abstract_game.get_state(node_id);
+            if !same_decision(game_info, &state, abstract_state) {
+                return None;
+            }
+            let abstract_action = match action {
+                Action::Raise(r) if abstract_game.get_child(node_id, action).is_none() => {
+                    translate_raise(method, game_info, &state, r, abstract_state, &abstract_game.get_actions(abstract_state), rng)?
+                },
+                _ => action,
+            };
+            abstract_game.get_child(node_id, abstract_action)
+        });
+        state = state.apply_action_no_cards(game_info, action)?;
+    }
+
+    // The bot can only use the node while it's at the same decision as the real game
+    let node_id = node_id.filter(|&n| same_decision(game_info, &state, abstract_game.get_state(n)));
+
+    Ok((state, node_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand_chacha::ChaCha8Rng;
+
+    use std::path::Path;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn pseudo_harmonic_endpoints_and_midpoint() {
+        assert_close(pseudo_harmonic(0.5, 1., 0.5), 1.);
+        assert_close(pseudo_harmonic(0.5, 1., 1.), 0.);
+        // ((B - x)(1 + A)) / ((B - A)(1 + x)) = (0.25 * 1.5) / (0.5 * 1.75)
+        assert_close(pseudo_harmonic(0.5, 1., 0.75), 3. / 7.);
+        assert_close(pseudo_harmonic(0.25, 1., 0.25), 1.);
+        assert_close(pseudo_harmonic(1., 1., 1.), 1.);
+    }
+
+    /// Heads up no limit hold'em before the small blind acts, the pot after calling is 200 so a
+    /// raise to 200 is half pot and a raise to 300 is pot
+    fn setup() -> (GameInfo, GameState, Vec<Action>) {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/holdem_nl.json"));
+        let state = GameState::new(&game_info, 0);
+        (game_info, state, vec![Action::Fold, Action::Call, Action::Raise(200), Action::Raise(300)])
+    }
+
+    #[test]
+    fn pot_fraction_counts_the_call() {
+        let (game_info, state, _) = setup();
+        assert_close(pot_fraction(&game_info, &state, 200), 0.5);
+        assert_close(pot_fraction(&game_info, &state, 300), 1.);
+    }
+
+    #[test]
+    fn nearest_and_pseudo_harmonic_selection() {
+        let (game_info, state, actions) = setup();
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let translate = |method, raise_to, rng: &mut ChaCha8Rng| translate_raise(method, &game_info, &state, raise_to, &state, &actions, rng);
+
+        // x = 0.74 is closer to half pot, but pseudo-harmonic maps it there with probability 0.45
+        assert_eq!(translate(TranslationMethod::Nearest, 248, &mut rng), Some(Action::Raise(200)));
+        assert_eq!(translate(TranslationMethod::PseudoHarmonic, 248, &mut rng), Some(Action::Raise(300)));
+        assert_eq!(translate(TranslationMethod::Nearest, 270, &mut rng), Some(Action::Raise(300)));
+        assert_eq!(translate(TranslationMethod::PseudoHarmonic, 220, &mut rng), Some(Action::Raise(200)));
+
+        // Outside the abstraction's range the closest end is used
+        assert_eq!(translate(TranslationMethod::PseudoHarmonic, 1000, &mut rng), Some(Action::Raise(300)));
+        assert_eq!(translate(TranslationMethod::Nearest, 150, &mut rng), Some(Action::Raise(200)));
+        assert_eq!(translate_raise(TranslationMethod::Nearest, &game_info, &state, 250, &state, &actions[..2], &mut rng), None);
+    }
+
+    #[test]
+    fn randomized_pseudo_harmonic_follows_the_mapping() {
+        let (game_info, state, actions) = setup();
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let samples = 20000;
+        let smaller = (0..samples)
+            .filter(|_| translate_raise(TranslationMethod::RandomizedPseudoHarmonic, &game_info, &state, 250, &state, &actions, &mut rng) == Some(Action::Raise(200)))
+            .count();
+        assert!((smaller as f64 / samples as f64 - 3. / 7.).abs() < 0.02);
+
+        // The same seed picks the same raises
+        let picks = |seed| {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            (0..50).map(|_| translate_raise(TranslationMethod::RandomizedPseudoHarmonic, &game_info, &state, 250, &state, &actions, &mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(picks(3), picks(3));
+    }
+}
@@ -0,0 +1,269 @@
+/*
+* Exact best response computation by walking the whole tree for every possible deal, only
+* feasible for small games like Kuhn and Leduc
+*/
+
+use super::{
+    abstract_game::AbstractGame,
+    game::{Action, GameState, PlayerId},
+    node::NodeId,
+    strategy::Strategy,
+};
+
+use poker::Card;
+
+use std::collections::BTreeMap;
+
+/// A deck a hand is dealt from and the state the hand is in, which tracks the cards dealt so far
+type Deal<'d> = (&'d [Card], GameState);
+
+/// Result of a best response computation
+#[derive(Debug)]
+pub struct Exploitability {
+    /// best_response_values[p] is the expected value of player p best responding to the strategy
+    pub best_response_values: Vec<f64>,
+    /// Average amount a best responder wins per game, in chips
+    pub exploitability: f64,
+    /// Exploitability in milli big blinds per game
+    pub mbb_per_game: f64,
+}
+
+pub struct BestResponse<'a> {
+    abstract_game: &'a AbstractGame,
+    strategy: &'a Strategy,
+    /// Every deck order that deals a different hand
+    decks: Vec<Vec<Card>>,
+}
+
+impl<'a> BestResponse<'a> {
+    pub fn new(abstract_game: &'a AbstractGame, strategy: &'a Strategy) -> BestResponse<'a> {
+        let decks = abstract_game.game_info.enumerate_deals();
+        info!("Enumerated {} deals for best response", decks.len());
+
+        BestResponse {
+            abstract_game,
+            strategy,
+            decks,
+        }
+    }
+
+    /// Computes best response values for every player and the resulting exploitability. For more
+    /// than two players the exploitability is the average gain of the best responders(NashConv
+    /// divided by the number of players) since the game is zero sum.
+    pub fn exploitability(&mut self) -> Exploitability {
+        let num_players = self.abstract_game.game_info.num_players();
+
+        let best_response_values: Vec<f64> = (0..num_players)
+            .map(|p| self.best_response_value(p))
+            .collect();
+
+        let exploitability = best_response_values.iter().sum::<f64>() / num_players as f64;
+        let mbb_per_game = exploitability / self.abstract_game.game_info.big_blind() as f64 * 1000.;
+
+        Exploitability {
+            best_response_values,
+            exploitability,
+            mbb_per_game,
+        }
+    }
+
+    /// Expected value for player when best responding to everyone else playing the strategy
+    pub fn best_response_value(&mut self, player: PlayerId) -> f64 {
+        let game_info = &self.abstract_game.game_info;
+        let root = self.abstract_game.get_root_node_id();
+        let root_state = self.abstract_game.get_state(root).clone().with_cards();
+        let deals: Vec<Deal> = self.decks.iter()
+            .map(|deck| (&deck[..], root_state.deal_from(game_info, deck).expect("failed to deal the root state")))
+            .collect();
+        let reach = vec![1.; deals.len()];
+
+        let values = self.values(root, &deals, &reach, player);
+        values.iter().sum::<f64>() / deals.len() as f64
+    }
+
+    /// Deals after action, with the cards due after it dealt
+    fn child_deals<'d>(&self, deals: &[&Deal<'d>], action: Action) -> Vec<Deal<'d>> {
+        let game_info = &self.abstract_game.game_info;
+        deals.iter()
+            .map(|&&(deck, ref state)| {
+                let child_state = state.apply_action_no_cards(game_info, action)
+                    .and_then(|s| s.deal_from(game_info, deck))
+                    .expect("failed to apply action");
+                (deck, child_state)
+            })
+            .collect()
+    }
+
+    /// Returns the value for player of each of deals at node_id, where reach[i] is the
+    /// probability the other players play to this node with deals[i]
+    fn values(&self, node_id: NodeId, deals: &[Deal], reach: &[f64], player: PlayerId) -> Vec<f64> {
+        let state = self.abstract_game.get_state(node_id);
+
+        if state.is_finished() || state.has_folded(player) {
+            return deals.iter()
+                .map(|(_, card_state)| card_state.get_dealt_payout(&self.abstract_game.game_info, player).expect("failed to get payout") as f64)
+                .collect();
+        }
+
+        let actions = self.abstract_game.get_actions(state);
+        let children: Vec<NodeId> = actions.iter()
+            .map(|&action| self.abstract_game.get_child(node_id, action).unwrap())
+            .collect();
+
+        let current_player = state.current_player().unwrap();
+
+        if current_player == player {
+            let all_deals: Vec<&Deal> = deals.iter().collect();
+            let action_values: Vec<Vec<f64>> = children.iter().zip(&actions)
+                .map(|(&child, &action)| self.values(child, &self.child_deals(&all_deals, action), reach, player))
+                .collect();
+
+            // The best responder can only condition on what it sees so deals are grouped by the
+            // player's hole cards and the board cards dealt so far
+            let mut infosets: BTreeMap<(Vec<Card>, Vec<Card>), Vec<usize>> = BTreeMap::new();
+            for (i, (_, card_state)) in deals.iter().enumerate() {
+                let mut hole_cards = card_state.hole_cards(player).to_vec();
+                hole_cards.sort();
+                let mut board_cards = card_state.board_cards().to_vec();
+                board_cards.sort();
+                infosets.entry((hole_cards, board_cards)).or_default().push(i);
+            }
+
+            let mut values = vec![0.; deals.len()];
+            for indices in infosets.values() {
+                let best_action = (0..actions.len())
+                    .map(|a| (a, indices.iter().map(|&i| reach[i] * action_values[a][i]).sum::<f64>()))
+                    .max_by(|x, y| x.1.total_cmp(&y.1))
+                    .unwrap()
+                    .0;
+
+                for &i in indices {
+                    values[i] = action_values[best_action][i];
+                }
+            }
+
+            values
+        } else {
+            let probabilities: Vec<Vec<f32>> = deals.iter()
+                .map(|(_, card_state)| {
+                    let bucket_id = self.abstract_game.get_state_bucket(card_state, current_player);
+                    self.strategy.probabilities(node_id, bucket_id, &actions)
+                })
+                .collect();
+
+            let mut values = vec![0.; deals.len()];
+            for (a, &child) in children.iter().enumerate() {
+                let mut child_deals = Vec::new();
+                let mut child_reach = Vec::new();
+                let mut indices = Vec::new();
+                for (i, deal) in deals.iter().enumerate() {
+                    let p = probabilities[i][a] as f64;
+                    if p > 0. {
+                        child_deals.push(deal);
+                        child_reach.push(reach[i] * p);
+                        indices.push(i);
+                    }
+                }
+
+                if child_deals.is_empty() {
+                    continue;
+                }
+
+                let child_values = self.values(child, &self.child_deals(&child_deals, actions[a]), &child_reach, player);
+                for (j, &i) in indices.iter().enumerate() {
+                    values[i] += probabilities[i][a] as f64 * child_values[j];
+                }
+            }
+
+            values
+        }
+    }
+}
+
+/// Convenience wrapper around `BestResponse`
+pub fn exploitability(abstract_game: &AbstractGame, strategy: &Strategy) -> Exploitability {
+    BestResponse::new(abstract_game, strategy).exploitability()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        action_abstraction::ActionAbstraction,
+        card_abstraction::CardAbstraction,
+        game::{GameInfo, GameState},
+    };
+
+    use std::path::Path;
+
+    fn kuhn() -> AbstractGame {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/kuhn.json"));
+        let state = GameState::new(&game_info, 0);
+        let action_abstraction = ActionAbstraction::from_config(Path::new("game_configs/kuhn_action_abstraction.json"));
+        let card_abstraction = CardAbstraction::from_config(Path::new("game_configs/kuhn_card_abstraction.json"));
+        AbstractGame::new(game_info, state, action_abstraction, card_abstraction)
+    }
+
+    /// Kuhn equilibrium where the first player never bets. Buckets are the card, 0 is the jack.
+    /// Probabilities are of fold or check, call and bet.
+    fn kuhn_equilibrium(abstract_game: &AbstractGame) -> Strategy {
+        let mut strategy = Strategy::new();
+        for (&node_id, node) in &abstract_game.nodes.nodes_map {
+            let state = &node.state;
+            let Ok(player) = state.current_player() else {
+                continue;
+            };
+            let facing_bet = state.max_spent() > state.player_spent(player);
+            let actions = abstract_game.get_actions(state);
+            for card in 0..3 {
+                let [fold, call, bet] = match (player, facing_bet, card) {
+                    // Opening, always checks
+                    (0, false, _) => [0., 1., 0.],
+                    // After a check, bluffs the jack a third of the time and bets the king
+                    (1, false, 0) => [0., 2. / 3., 1. / 3.],
+                    (1, false, 1) => [0., 1., 0.],
+                    (1, false, _) => [0., 0., 1.],
+                    // Facing a bet, calls the queen a third of the time
+                    (_, true, 0) => [1., 0., 0.],
+                    (_, true, 1) => [2. / 3., 1. / 3., 0.],
+                    (_, true, _) => [0., 1., 0.],
+                    _ => unreachable!(),
+                };
+                let weights = actions.iter()
+                    .map(|action| match action {
+                        Action::Fold => fold,
+                        Action::Call => call,
+                        Action::Raise(_) => bet,
+                    })
+                    .collect();
+                strategy.0.insert((node_id, card), weights);
+            }
+        }
+        strategy
+    }
+
+    #[test]
+    fn uniform_random_kuhn_exploitability() {
+        let abstract_game = kuhn();
+        let result = exploitability(&abstract_game, &Strategy::new());
+        // Uniform play folds a third of the time with nothing to call. The first player best
+        // responds by checking the jack(-1/3) and betting the queen(1/2) and king(3/2), 5/9 a
+        // game. The second player bets after a check and calls a bet with the queen and king,
+        // so the jack is worth -1/6, the queen 1/2 and the king 3/2, 11/18 a game.
+        assert!((result.best_response_values[0] - 5. / 9.).abs() < 1e-6, "{:?}", result);
+        assert!((result.best_response_values[1] - 11. / 18.).abs() < 1e-6, "{:?}", result);
+        assert!((result.exploitability - 7. / 12.).abs() < 1e-6, "{:?}", result);
+    }
+
+    #[test]
+    fn kuhn_equilibrium_is_unexploitable() {
+        let abstract_game = kuhn();
+        let strategy = kuhn_equilibrium(&abstract_game);
+        let result = exploitability(&abstract_game, &strategy);
+        // Best responding to an equilibrium earns the game value, -1/18 for the first player
+        assert!((result.best_response_values[0] + 1. / 18.).abs() < 1e-6, "{:?}", result);
+        assert!((result.best_response_values[1] - 1. / 18.).abs() < 1e-6, "{:?}", result);
+        assert!(result.exploitability.abs() < 1e-6, "{:?}", result);
+    }
+}
@@ -1,24 +1,165 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
 use std::path::PathBuf;
 
-use ungar::{*, cfr::{CFREngine, CFRConfig}, abstract_game::AbstractGame, play::play, strategy::Strategy};
+use ungar::{*, clustering::{build_emd_table, EmdAbstractionConfig}, dealer::DealerConfig, accumulator::{Accumulator, AccumulatorType}, action_translation::TranslationMethod, cfr::{CFREngine, CFRConfig}, action_abstraction::ActionAbstraction, card_abstraction::CardAbstraction, game::{GameInfo, GameState}, abstract_game::AbstractGame, best_response::exploitability, play::play, strategy::Strategy};
 
-use clap::{Parser, Subcommand};
+use clap::{Args as ClapArgs, Parser, Subcommand};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// Values that override the ones in the cfr config
+#[derive(ClapArgs, Debug)]
+struct CFRConfigOverrides {
+    #[arg(long)]
+    ticks: Option<u32>,
+    #[arg(long)]
+    strategy_interval: Option<u32>,
+    #[arg(long)]
+    prune_threshold: Option<u32>,
+    #[arg(long)]
+    lcfr_threshold: Option<u32>,
+    #[arg(long)]
+    discount_interval: Option<u32>,
+    #[arg(long)]
+    prune_probability: Option<f32>,
+    #[arg(long, allow_hyphen_values = true)]
+    regret_prune_threshold: Option<f64>,
+    #[arg(long)]
+    checkpoint_interval: Option<u32>,
+    #[arg(long)]
+    num_threads: Option<usize>,
+    /// Only used by fresh runs, resumed runs carry on with the checkpoint's random state
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+impl CFRConfigOverrides {
+    fn apply(&self, cfr_config: &mut CFRConfig) {
+        if let Some(ticks) = self.ticks {
+            cfr_config.ticks = ticks;
+        }
+        if let Some(strategy_interval) = self.strategy_interval {
+            cfr_config.strategy_interval = strategy_interval;
+        }
+        if let Some(prune_threshold) = self.prune_threshold {
+            cfr_config.prune_threshold = prune_threshold;
+        }
+        if let Some(lcfr_threshold) = self.lcfr_threshold {
+            cfr_config.lcfr_threshold = lcfr_threshold;
+        }
+        if let Some(discount_interval) = self.discount_interval {
+            cfr_config.discount_interval = discount_interval;
+        }
+        if let Some(prune_probability) = self.prune_probability {
+            cfr_config.prune_probability = prune_probability;
+        }
+        if let Some(regret_prune_threshold) = self.regret_prune_threshold {
+            cfr_config.regret_prune_threshold = regret_prune_threshold;
+        }
+        if let Some(checkpoint_interval) = self.checkpoint_interval {
+            cfr_config.checkpoint_interval = Some(checkpoint_interval);
+        }
+        if let Some(num_threads) = self.num_threads {
+            cfr_config.num_threads = num_threads;
+        }
+        if let Some(seed) = self.seed {
+            cfr_config.seed = Some(seed);
+        }
+        cfr_config.validate().expect("invalid cfr config");
+    }
+}
 
 #[derive(Subcommand, Debug)]
 enum Commands {
     Train {
-        #[arg(long)]
-        cfr_config: PathBuf,
+        #[arg(long, required_unless_present = "resume")]
+        cfr_config: Option<PathBuf>,
         #[arg(long)]
         output_strategy_path: Option<PathBuf>,
         #[arg(long)]
         output_nodes_path: Option<PathBuf>,
+        /// Where checkpoints get written, defaults to the resumed checkpoint
+        #[arg(long)]
+        checkpoint_path: Option<PathBuf>,
+        /// Checkpoint to continue training from
+        #[arg(long)]
+        resume: Option<PathBuf>,
+        #[command(flatten)]
+        overrides: CFRConfigOverrides,
     },
     Play {
         #[arg(short, long)]
         strategy_path: PathBuf,
         #[arg(short, long)]
         nodes_path: PathBuf,
+        /// How raises outside the action abstraction are mapped onto it
+        #[arg(long, value_enum, default_value_t = TranslationMethod::PseudoHarmonic)]
+        translation: TranslationMethod,
+        /// Seed of the bot's random choices, random if not given
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Plays the strategy in matches run by an ACPC dealer
+    Client {
+        #[arg(short, long)]
+        strategy_path: PathBuf,
+        #[arg(short, long)]
+        nodes_path: PathBuf,
+        #[arg(long)]
+        host: String,
+        #[arg(long)]
+        port: u16,
+        /// How raises outside the action abstraction are mapped onto it
+        #[arg(long, value_enum, default_value_t = TranslationMethod::PseudoHarmonic)]
+        translation: TranslationMethod,
+        /// Seed of the bot's random choices, random if not given
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Runs an ACPC match between agents connecting to port, in the order they connect
+    Dealer {
+        #[arg(long)]
+        port: u16,
+        #[arg(long, default_value_t = 1000)]
+        num_hands: u32,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// Match log, one line per hand, printed if not given
+        #[arg(long)]
+        log_path: Option<PathBuf>,
+    },
+    /// Computes exact best response values against a strategy, only feasible for small games
+    Exploitability {
+        #[arg(short, long)]
+        strategy_path: PathBuf,
+        #[arg(short, long)]
+        nodes_path: PathBuf,
+    },
+    /// Clusters every hand of a round into potential aware buckets and writes the bucket table
+    /// EmdBuckets loads
+    BuildAbstraction {
+        #[arg(long)]
+        round: u8,
+        #[arg(long)]
+        num_buckets: u32,
+        #[arg(long)]
+        output_path: PathBuf,
+        #[arg(long, default_value_t = 30)]
+        num_bins: usize,
+        #[arg(long, default_value_t = 16)]
+        next_round_samples: u32,
+        #[arg(long, default_value_t = 8)]
+        rollout_samples: u32,
+        #[arg(long, default_value_t = 8)]
+        opponent_samples: u32,
+        #[arg(long, default_value_t = 20)]
+        iterations: u32,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        #[arg(long, default_value_t = 1)]
+        num_threads: usize,
     },
 }
 
@@ -29,29 +170,46 @@ struct Args {
     #[arg(short, long)]
     game_config: PathBuf,
 
+    /// Required by every command but build-abstraction
     #[arg(short, long)]
-    action_abstraction_config: PathBuf,
+    action_abstraction_config: Option<PathBuf>,
 
+    /// Required by every command but build-abstraction
     #[arg(short, long)]
-    card_abstraction_config: PathBuf,
+    card_abstraction_config: Option<PathBuf>,
 
     /// Command
     #[command(subcommand)]
     command: Commands,
 }
 
-fn train(abstract_game: AbstractGame, cfr_config: CFRConfig, output_strategy_path: Option<PathBuf>, output_nodes_path: Option<PathBuf>) {
-    let mut cfr_engine = CFREngine::new(abstract_game, cfr_config);
+/// Builds a fresh or resumed engine storing regrets as T and trains it
+#[allow(clippy::too_many_arguments)]
+fn train<T: Accumulator>(game_info: GameInfo, starting_state: GameState, action_abstraction: ActionAbstraction, card_abstraction: CardAbstraction, cfr_config: CFRConfig, resume: Option<PathBuf>, overrides: CFRConfigOverrides, checkpoint_path: Option<PathBuf>, output_strategy_path: Option<PathBuf>, output_nodes_path: Option<PathBuf>) {
+    let mut cfr_engine: CFREngine<T> = match &resume {
+        Some(resume) => {
+            let mut cfr_engine = CFREngine::from_checkpoint(resume, game_info, action_abstraction, card_abstraction);
+            overrides.apply(cfr_engine.config_mut());
+            cfr_engine
+        },
+        None => {
+            // Overrides go in before the engine is built so the seed override takes effect
+            let mut cfr_config = cfr_config;
+            overrides.apply(&mut cfr_config);
+            let abstract_game = AbstractGame::new(game_info, starting_state, action_abstraction, card_abstraction);
+            CFREngine::new(abstract_game, cfr_config)
+        },
+    };
 
-    cfr_engine.mccfr_p(150000, 20, 400, 100000, 2500);
+    let checkpoint_path = checkpoint_path.or(resume);
+    cfr_engine.train(checkpoint_path.as_deref());
     match output_strategy_path {
         Some(p) => cfr_engine.save_average_strategy(&p),
         None => cfr_engine.print_average_strategy(),
     };
-    match output_nodes_path {
-        Some(p) => cfr_engine.save_nodes(&p),
-        None => (),
-    };
+    if let Some(p) = output_nodes_path {
+        cfr_engine.save_nodes(&p);
+    }
     cfr_engine.print_regrets();
     cfr_engine.print_average_strategy()
 }
@@ -63,20 +221,63 @@ fn main() {
 
     let game_info = game::GameInfo::load_game_info(&args.game_config);
     let starting_state = game::GameState::new(&game_info, 0);
-    let action_abstraction = action_abstraction::ActionAbstraction::from_config(&args.action_abstraction_config);
-    let card_abstraction = card_abstraction::CardAbstraction::from_config(&args.card_abstraction_config);
+    let load_abstractions = || (
+        ActionAbstraction::from_config(args.action_abstraction_config.as_ref().expect("action abstraction config is required")),
+        CardAbstraction::from_config(args.card_abstraction_config.as_ref().expect("card abstraction config is required")),
+    );
 
     match args.command {
-        Commands::Train { cfr_config, output_strategy_path, output_nodes_path }=> {
-            let abstract_game = AbstractGame::new(game_info, starting_state, action_abstraction, card_abstraction);
-            let cfr_config = CFRConfig::from_config(&cfr_config);
-            train(abstract_game, cfr_config, output_strategy_path, output_nodes_path);
+        Commands::Train { cfr_config, output_strategy_path, output_nodes_path, checkpoint_path, resume, overrides } => {
+            let (action_abstraction, card_abstraction) = load_abstractions();
+            // The accumulator type of a resumed run is whatever the checkpoint was written with
+            let cfr_config = match &resume {
+                Some(resume) => CFRConfig::from_checkpoint(resume),
+                None => CFRConfig::from_config(&cfr_config.expect("cfr config is required when not resuming")),
+            };
+            match cfr_config.accumulator {
+                AccumulatorType::F32 => train::<f32>(game_info, starting_state, action_abstraction, card_abstraction, cfr_config, resume, overrides, checkpoint_path, output_strategy_path, output_nodes_path),
+                AccumulatorType::F64 => train::<f64>(game_info, starting_state, action_abstraction, card_abstraction, cfr_config, resume, overrides, checkpoint_path, output_strategy_path, output_nodes_path),
+                AccumulatorType::I32 => train::<i32>(game_info, starting_state, action_abstraction, card_abstraction, cfr_config, resume, overrides, checkpoint_path, output_strategy_path, output_nodes_path),
+            }
         },
-        Commands::Play { strategy_path, nodes_path } => {
-            let mut abstract_game = AbstractGame::load_nodes(game_info, &nodes_path, action_abstraction, card_abstraction);
+        Commands::Play { strategy_path, nodes_path, translation, seed } => {
+            let (action_abstraction, card_abstraction) = load_abstractions();
+            let abstract_game = AbstractGame::load_nodes(game_info, &nodes_path, action_abstraction, card_abstraction);
             let strategy = Strategy::from_file(&strategy_path);
-            play(&mut abstract_game, strategy);
-        }
+            let mut rng = ChaCha8Rng::seed_from_u64(seed.unwrap_or_else(rand::random));
+            play(&abstract_game, strategy, translation, &mut rng);
+        },
+        Commands::Client { strategy_path, nodes_path, host, port, translation, seed } => {
+            let (action_abstraction, card_abstraction) = load_abstractions();
+            let abstract_game = AbstractGame::load_nodes(game_info, &nodes_path, action_abstraction, card_abstraction);
+            let strategy = Strategy::from_file(&strategy_path);
+            client::connect(&abstract_game, &strategy, translation, seed.unwrap_or_else(rand::random), &host, port).expect("failed to play match");
+        },
+        Commands::Dealer { port, num_hands, seed, log_path } => {
+            let config = DealerConfig { num_hands, seed };
+            let totals = match log_path {
+                Some(p) => dealer::serve(&game_info, &config, port, &mut BufWriter::new(File::create(p).expect("failed to create match log"))),
+                None => dealer::serve(&game_info, &config, port, &mut io::stdout()),
+            }.expect("failed to run match");
+            for (a, total) in totals.iter().enumerate() {
+                println!("Agent {} total payout: {} ({} per hand)", a, total, *total as f64 / num_hands.max(1) as f64);
+            }
+        },
+        Commands::Exploitability { strategy_path, nodes_path } => {
+            let (action_abstraction, card_abstraction) = load_abstractions();
+            let abstract_game = AbstractGame::load_nodes(game_info, &nodes_path, action_abstraction, card_abstraction);
+            let strategy = Strategy::from_file(&strategy_path);
+            let result = exploitability(&abstract_game, &strategy);
+            for (p, v) in result.best_response_values.iter().enumerate() {
+                println!("Player {} best response value: {}", p, v);
+            }
+            println!("Exploitability: {} chips/game ({} mbb/g)", result.exploitability, result.mbb_per_game);
+        },
+        Commands::BuildAbstraction { round, num_buckets, output_path, num_bins, next_round_samples, rollout_samples, opponent_samples, iterations, seed, num_threads } => {
+            let config = EmdAbstractionConfig { round, num_buckets, num_bins, next_round_samples, rollout_samples, opponent_samples, iterations, seed, num_threads };
+            let table = build_emd_table(&game_info, &config);
+            table.save(&output_path);
+        },
     }
 
 }
@@ -0,0 +1,247 @@
+use super::{
+    card_abstraction::BucketId,
+    hand_indexer::HandIndexer,
+};
+
+use serde::{Serialize, Deserialize};
+
+use poker::Card;
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: [u8; 8] = *b"UNGARBKT";
+const VERSION: u32 = 3;
+
+/// Written in front of the buckets so a table can be checked against the game before use
+#[derive(Debug, Serialize, Deserialize)]
+struct BucketTableHeader {
+    magic: [u8; 8],
+    version: u32,
+    num_suits: u8,
+    num_ranks: u8,
+    lowest_rank: u8,
+    round: u8,
+    cards_per_round: Vec<u8>,
+    num_buckets: u32,
+    source_hash: u64,
+    num_entries: u64,
+    /// Bytes each bucket is stored in, the fewest that fit num_buckets
+    bytes_per_entry: u8,
+    /// FNV-1a hash of the bucket bytes
+    checksum: u64,
+}
+
+/// Buckets stored in the smallest integer type that holds every bucket id
+#[derive(Debug)]
+enum Buckets {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+/// Precomputed bucket of every suit isomorphic hand of one round, looked up by hand index
+#[derive(Debug)]
+pub struct BucketTable {
+    pub num_suits: u8,
+    pub num_ranks: u8,
+    pub lowest_rank: u8,
+    pub round: u8,
+    /// Rounds the hands are indexed over, see `card_abstraction::cards_per_round`
+    pub cards_per_round: Vec<u8>,
+    pub num_buckets: u32,
+    /// Hash of the config of the abstraction the buckets come from, see `config_hash`. 0 when
+    /// they don't come from a `RoundBuckets`.
+    pub source_hash: u64,
+    buckets: Buckets,
+}
+
+impl BucketTable {
+    /// buckets[i] is the bucket of the hand with index i in the last round of cards_per_round
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(num_suits: u8, num_ranks: u8, lowest_rank: u8, round: u8, cards_per_round: Vec<u8>, num_buckets: u32, source_hash: u64, buckets: Vec<BucketId>) -> BucketTable {
+        let buckets = if num_buckets <= u8::MAX as u32 + 1 {
+            Buckets::U8(buckets.into_iter().map(|b| b as u8).collect())
+        } else if num_buckets <= u16::MAX as u32 + 1 {
+            Buckets::U16(buckets.into_iter().map(|b| b as u16).collect())
+        } else {
+            Buckets::U32(buckets)
+        };
+
+        BucketTable { num_suits, num_ranks, lowest_rank, round, cards_per_round, num_buckets, source_hash, buckets }
+    }
+
+    pub fn from_file(path: &Path) -> BucketTable {
+        BucketTable::load(path).expect("failed to load bucket table")
+    }
+
+    /// Reads a table and checks its header and checksum
+    pub fn load(path: &Path) -> Result<BucketTable, &'static str> {
+        let mut r = BufReader::new(File::open(path).map_err(|_| "failed to open bucket table")?);
+        let header: BucketTableHeader = bincode::deserialize_from(&mut r).map_err(|_| "failed to deserialize bucket table header")?;
+        if header.magic != MAGIC {
+            return Err("not a bucket table");
+        }
+        if header.version != VERSION {
+            return Err("unsupported bucket table version");
+        }
+
+        let num_bytes = usize::try_from(header.num_entries)
+            .ok()
+            .and_then(|n| n.checked_mul(header.bytes_per_entry as usize))
+            .ok_or("bucket table too large")?;
+        let mut bytes = vec![0u8; num_bytes];
+        r.read_exact(&mut bytes).map_err(|_| "bucket table is truncated")?;
+        if r.read(&mut [0u8])
+            .map_err(|_| "failed to read bucket table")? != 0 {
+            return Err("bucket table has trailing data");
+        }
+        if fnv1a(&bytes) != header.checksum {
+            return Err("bucket table checksum mismatch");
+        }
+
+        let buckets = match header.bytes_per_entry {
+            1 => Buckets::U8(bytes),
+            2 => Buckets::U16(bytes.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect()),
+            4 => Buckets::U32(bytes.chunks_exact(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()),
+            _ => return Err("invalid bucket table entry size"),
+        };
+
+        Ok(BucketTable {
+            num_suits: header.num_suits,
+            num_ranks: header.num_ranks,
+            lowest_rank: header.lowest_rank,
+            round: header.round,
+            cards_per_round: header.cards_per_round,
+            num_buckets: header.num_buckets,
+            source_hash: header.source_hash,
+            buckets,
+        })
+    }
+
+    /// Writes the table through a temporary file so a crash never leaves a partial table behind
+    pub fn save(&self, path: &Path) {
+        let (bytes_per_entry, bytes): (u8, Vec<u8>) = match &self.buckets {
+            Buckets::U8(b) => (1, b.clone()),
+            Buckets::U16(b) => (2, b.iter().flat_map(|v| v.to_le_bytes()).collect()),
+            Buckets::U32(b) => (4, b.iter().flat_map(|v| v.to_le_bytes()).collect()),
+        };
+        let header = BucketTableHeader {
+            magic: MAGIC,
+            version: VERSION,
+            num_suits: self.num_suits,
+            num_ranks: self.num_ranks,
+            lowest_rank: self.lowest_rank,
+            round: self.round,
+            cards_per_round: self.cards_per_round.clone(),
+            num_buckets: self.num_buckets,
+            source_hash: self.source_hash,
+            num_entries: self.len() as u64,
+            bytes_per_entry,
+            checksum: fnv1a(&bytes),
+        };
+
+        let tmp_path = path.with_extension("tmp");
+        let mut f = BufWriter::new(File::create(&tmp_path).expect("failed to create bucket table"));
+        bincode::serialize_into(&mut f, &header).expect("failed to serialize bucket table header");
+        f.write_all(&bytes).expect("failed to write bucket table");
+        f.flush().expect("failed to write bucket table");
+        drop(f);
+        fs::rename(&tmp_path, path).expect("failed to move bucket table into place");
+    }
+
+    /// Number of hands in the table
+    pub fn len(&self) -> usize {
+        match &self.buckets {
+            Buckets::U8(b) => b.len(),
+            Buckets::U16(b) => b.len(),
+            Buckets::U32(b) => b.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn indexer(&self) -> HandIndexer {
+        HandIndexer::new(self.num_suits, self.num_ranks, self.lowest_rank, &self.cards_per_round)
+    }
+
+    /// Bucket of the hand with index
+    pub fn get(&self, index: u64) -> BucketId {
+        let i = index as usize;
+        match &self.buckets {
+            Buckets::U8(b) => b[i] as BucketId,
+            Buckets::U16(b) => b[i] as BucketId,
+            Buckets::U32(b) => b[i],
+        }
+    }
+
+    /// Bucket of cards, hole cards first, indexed with the table's indexer
+    pub fn get_bucket(&self, indexer: &HandIndexer, cards: &[Card]) -> BucketId {
+        self.get(indexer.index(cards))
+    }
+}
+
+/// Hash of an abstraction's serialized config, tables built from differently configured
+/// abstractions get different hashes
+pub fn config_hash<T: Serialize + ?Sized>(config: &T) -> u64 {
+    fnv1a(&serde_json::to_vec(config).expect("failed to serialize abstraction config"))
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("ungar_{}_{}.bkt", name, std::process::id()))
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("round_trip");
+        for num_buckets in [3, 300, 70000] {
+            let buckets: Vec<BucketId> = (0..3).map(|b| b * (num_buckets - 1) / 2).collect();
+            BucketTable::new(2, 3, 9, 0, vec![1], num_buckets, 42, buckets.clone()).save(&path);
+
+            let table = BucketTable::load(&path).unwrap();
+            assert_eq!((table.num_suits, table.num_ranks, table.lowest_rank, table.round), (2, 3, 9, 0));
+            assert_eq!(table.cards_per_round, vec![1]);
+            assert_eq!((table.num_buckets, table.source_hash), (num_buckets, 42));
+            assert_eq!((0..3).map(|i| table.get(i)).collect::<Vec<_>>(), buckets);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_tables_are_rejected() {
+        let path = temp_path("corrupt");
+        BucketTable::new(2, 3, 0, 0, vec![1], 3, 0, vec![0, 1, 2]).save(&path);
+        let bytes = fs::read(&path).unwrap();
+
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        fs::write(&path, &flipped).unwrap();
+        assert_eq!(BucketTable::load(&path).unwrap_err(), "bucket table checksum mismatch");
+
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(BucketTable::load(&path).unwrap_err(), "bucket table is truncated");
+
+        fs::write(&path, [&bytes[..], &[0]].concat()).unwrap();
+        assert_eq!(BucketTable::load(&path).unwrap_err(), "bucket table has trailing data");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn config_hash_tells_configs_apart() {
+        assert_eq!(config_hash(&(1, "ehs")), config_hash(&(1, "ehs")));
+        assert_ne!(config_hash(&(1, "ehs")), config_hash(&(1, "ehs2")));
+    }
+}
@@ -1,11 +1,25 @@
-use super::game::GameInfo;
+use super::{
+    bucket_table::{config_hash, BucketTable},
+    equity::expected_hand_strength,
+    game::{generate_deck, GameInfo},
+    hand_indexer::HandIndexer,
+};
 
-use std::path::Path;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::sync::{Mutex, OnceLock};
+use std::thread;
 
 use serde::{Deserialize, Serialize};
 
-use poker::Card;
+use poker::{Card, Evaluator, Rank};
+
+use variter::VarIter;
+
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
 
 pub type BucketId = u32;
 
@@ -30,17 +44,34 @@ impl CardAbstraction {
     pub fn get_bucket(&self, round: u8, board_cards: &[Card], hole_cards: &[Card]) -> BucketId {
         self.round_infosets[round as usize].get_bucket(board_cards, hole_cards)
     }
+
+    pub fn num_buckets(&self, round: u8) -> u64 {
+        self.round_infosets[round as usize].num_buckets()
+    }
+}
+
+/// Hole cards followed by the board cards of each round up to round that deals any, the rounds
+/// hands are indexed over
+pub fn cards_per_round(game_info: &GameInfo, round: u8) -> Vec<u8> {
+    let mut cards_per_round = vec![game_info.num_hole_cards()];
+    cards_per_round.extend((0..=round).map(|r| game_info.num_board_cards(r)).filter(|&c| c > 0));
+    cards_per_round
 }
 
 #[typetag::serde(tag = "type")]
-pub trait RoundBuckets {
+pub trait RoundBuckets: Send + Sync {
     fn get_bucket(&self, board_cards: &[Card], hole_cards: &[Card]) -> BucketId;
+
+    /// Number of distinct bucket ids get_bucket can return
+    fn num_buckets(&self) -> u64;
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct NoBuckets {
     num_suits: u8,
     num_ranks: u8,
+    #[serde(default)]
+    lowest_rank: u8,
     num_board_cards: u8,
     num_hole_cards: u8,
 }
@@ -50,6 +81,7 @@ impl NoBuckets {
         NoBuckets {
             num_suits: game_info.num_suits(),
             num_ranks: game_info.num_ranks(),
+            lowest_rank: game_info.lowest_rank(),
             num_board_cards: game_info.total_board_cards(round),
             num_hole_cards: game_info.num_hole_cards(), 
         }
@@ -59,46 +91,683 @@ impl NoBuckets {
 #[typetag::serde]
 impl RoundBuckets for NoBuckets {
     fn get_bucket(&self, board_cards: &[Card], hole_cards: &[Card]) -> BucketId {
+        let card_id = |c: &Card| (c.rank() as u32 - self.lowest_rank as u32) * self.num_suits as u32 + c.suit() as u32;
         let mut bucket: BucketId = 0;
         for i in 0..self.num_hole_cards {
             if i > 0 {
                 bucket *= self.num_suits as u32 * self.num_ranks as u32;
             }
-            bucket += hole_cards[i as usize].rank() as u32 * self.num_suits as u32 + hole_cards[i as usize].suit() as u32;
+            bucket += card_id(&hole_cards[i as usize]);
         }
 
         for i in 0..self.num_board_cards {
             bucket *= self.num_suits as u32 * self.num_ranks as u32;
-            bucket += board_cards[i as usize].rank() as u32 * self.num_suits as u32 + board_cards[i as usize].suit() as u32;
+            bucket += card_id(&board_cards[i as usize]);
         }
 
         bucket
     }
+
+    fn num_buckets(&self) -> u64 {
+        (self.num_suits as u64 * self.num_ranks as u64).pow((self.num_hole_cards + self.num_board_cards) as u32)
+    }
 }
 
+/// Suit isomorphic buckets, hands that only differ by a permutation of suits share a bucket
 #[derive(Serialize, Deserialize)]
 pub struct LosslessBuckets {
     num_suits: u8,
     num_ranks: u8,
-    num_board_cards: u8,
-    num_hole_cards: u8,
+    #[serde(default)]
+    lowest_rank: u8,
+    /// Hole cards followed by the board cards of each round with any, up to this round
+    cards_per_round: Vec<u8>,
+    /// Built on first use, the tables are cheap to rebuild so they aren't serialized
+    #[serde(skip)]
+    indexer: OnceLock<HandIndexer>,
 }
 
 impl LosslessBuckets {
     pub fn new(game_info: &GameInfo, round: u8) -> LosslessBuckets {
+        let cards_per_round = cards_per_round(game_info, round);
+
         LosslessBuckets {
             num_suits: game_info.num_suits(),
             num_ranks: game_info.num_ranks(),
-            num_board_cards: game_info.total_board_cards(round),
-            num_hole_cards: game_info.num_hole_cards(), 
+            lowest_rank: game_info.lowest_rank(),
+            cards_per_round,
+            indexer: OnceLock::new(),
         }
     }
+
+    fn indexer(&self) -> &HandIndexer {
+        self.indexer.get_or_init(|| HandIndexer::new(self.num_suits, self.num_ranks, self.lowest_rank, &self.cards_per_round))
+    }
 }
 
 #[typetag::serde]
 impl RoundBuckets for LosslessBuckets {
     fn get_bucket(&self, board_cards: &[Card], hole_cards: &[Card]) -> BucketId {
-        //TODO: implement lossless(suit isomprhims etc) abstraction, look at http://www.kevinwaugh.com/pdf/isomorphism13.pdf
-        0
+        let num_board_cards = self.cards_per_round[1..].iter().map(|&c| c as usize).sum::<usize>();
+        let mut cards = Vec::with_capacity(hole_cards.len() + num_board_cards);
+        cards.extend_from_slice(hole_cards);
+        cards.extend_from_slice(&board_cards[..num_board_cards]);
+
+        BucketId::try_from(self.indexer().index(&cards)).expect("hand index doesn't fit in a bucket id")
+    }
+
+    fn num_buckets(&self) -> u64 {
+        let indexer = self.indexer();
+        indexer.round_size(indexer.num_rounds() - 1)
+    }
+}
+
+/// The standard preflop classes of two card hands: pairs, suited and offsuit hands. Bucket
+/// high * num_ranks + low is suited, low * num_ranks + high offsuit and rank * num_ranks + rank
+/// the pair, so with 13 ranks the buckets are the cells of the usual 13x13 chart.
+#[derive(Serialize, Deserialize)]
+pub struct PreflopBuckets {
+    num_ranks: u8,
+    #[serde(default)]
+    lowest_rank: u8,
+}
+
+impl PreflopBuckets {
+    pub fn new(game_info: &GameInfo) -> PreflopBuckets {
+        assert!(game_info.num_hole_cards() == 2, "preflop buckets need two hole cards");
+        PreflopBuckets { num_ranks: game_info.num_ranks(), lowest_rank: game_info.lowest_rank() }
+    }
+
+    /// Label of a bucket like "AKs", "T9o" or "22"
+    pub fn label(&self, bucket: BucketId) -> String {
+        preflop_label(self.num_ranks, self.lowest_rank, bucket)
+    }
+}
+
+/// Label of a PreflopBuckets bucket of a game with num_ranks ranks from lowest_rank up
+pub fn preflop_label(num_ranks: u8, lowest_rank: u8, bucket: BucketId) -> String {
+    let n = num_ranks as u32;
+    let (row, column) = (bucket / n, bucket % n);
+    let rank = |r: u32| Rank::ALL_VARIANTS[(lowest_rank as u32 + r) as usize].as_char();
+    match row.cmp(&column) {
+        Ordering::Equal => format!("{}{}", rank(row), rank(row)),
+        Ordering::Greater => format!("{}{}s", rank(row), rank(column)),
+        Ordering::Less => format!("{}{}o", rank(column), rank(row)),
+    }
+}
+
+#[typetag::serde]
+impl RoundBuckets for PreflopBuckets {
+    fn get_bucket(&self, _board_cards: &[Card], hole_cards: &[Card]) -> BucketId {
+        let (a, b) = (hole_cards[0], hole_cards[1]);
+        let (a_rank, b_rank) = (a.rank() as u32 - self.lowest_rank as u32, b.rank() as u32 - self.lowest_rank as u32);
+        let (high, low) = if a_rank >= b_rank { (a_rank, b_rank) } else { (b_rank, a_rank) };
+        let n = self.num_ranks as u32;
+        if a.suit() == b.suit() {
+            high * n + low
+        } else {
+            low * n + high
+        }
+    }
+
+    fn num_buckets(&self) -> u64 {
+        self.num_ranks as u64 * self.num_ranks as u64
+    }
+}
+
+/// Hand strength measure EhsBuckets buckets by
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum HandStrengthMetric {
+    Ehs,
+    /// Expected squared hand strength, separates drawing hands from made hands of the same EHS
+    Ehs2,
+}
+
+/// How bucket boundaries are placed over hand strength
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum BucketEdges {
+    /// Equal width intervals of [0, 1]
+    Uniform,
+    /// Each bucket gets about the same share of hands, estimated from edge_samples random hands
+    Percentile,
+}
+
+/// Buckets hands by expected hand strength against a uniformly random opponent hand. Strength is
+/// estimated by seeded Monte Carlo on the canonical suit isomorphic hand so every hand always
+/// lands in the same bucket. Hole cards plus the final board have to be at least five cards.
+#[derive(Serialize, Deserialize)]
+pub struct EhsBuckets {
+    num_suits: u8,
+    num_ranks: u8,
+    #[serde(default)]
+    lowest_rank: u8,
+    /// Hole cards followed by the board cards of each round with any, up to this round
+    cards_per_round: Vec<u8>,
+    /// Board cards once every round has been dealt
+    total_board_cards: u8,
+    metric: HandStrengthMetric,
+    num_buckets: u32,
+    edges: BucketEdges,
+    /// Board rollouts per hand
+    #[serde(default = "EhsBuckets::default_board_samples")]
+    board_samples: u32,
+    /// Opponent hands per rollout
+    #[serde(default = "EhsBuckets::default_opponent_samples")]
+    opponent_samples: u32,
+    /// Random hands used to place percentile edges
+    #[serde(default = "EhsBuckets::default_edge_samples")]
+    edge_samples: u32,
+    #[serde(default)]
+    seed: u64,
+    #[serde(skip)]
+    tables: OnceLock<EhsTables>,
+}
+
+struct EhsTables {
+    indexer: HandIndexer,
+    evaluator: Evaluator,
+    deck: Vec<Card>,
+    /// Upper bounds of every bucket but the last
+    edges: Vec<f64>,
+    strengths: StrengthCache,
+}
+
+/// Strengths of suit isomorphic hands computed so far. Split over separately locked shards so
+/// training threads rarely wait on each other, and each shard is cleared once it's full so the
+/// cache stays bounded on the turn and river. Strengths are seeded by hand index, so a cleared
+/// hand is recomputed to the same value. Use PrecomputedBuckets to skip computing them in training.
+struct StrengthCache {
+    shards: Vec<Mutex<HashMap<u64, f64>>>,
+}
+
+impl StrengthCache {
+    const NUM_SHARDS: usize = 64;
+    const MAX_SHARD_LEN: usize = 1 << 14;
+
+    fn new() -> StrengthCache {
+        StrengthCache {
+            shards: (0..Self::NUM_SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+
+    /// Cached strength of index, computed with f under the shard's lock on a miss
+    fn get_or_insert_with<F: FnOnce() -> f64>(&self, index: u64, f: F) -> f64 {
+        let mut shard = self.shards[index as usize % Self::NUM_SHARDS].lock().unwrap();
+        if let Some(&strength) = shard.get(&index) {
+            return strength;
+        }
+
+        if shard.len() >= Self::MAX_SHARD_LEN {
+            shard.clear();
+        }
+        let strength = f();
+        shard.insert(index, strength);
+        strength
+    }
+}
+
+impl EhsBuckets {
+    pub fn new(game_info: &GameInfo, round: u8, metric: HandStrengthMetric, num_buckets: u32, edges: BucketEdges) -> EhsBuckets {
+        let cards_per_round = cards_per_round(game_info, round);
+
+        EhsBuckets {
+            num_suits: game_info.num_suits(),
+            num_ranks: game_info.num_ranks(),
+            lowest_rank: game_info.lowest_rank(),
+            cards_per_round,
+            total_board_cards: game_info.total_board_cards(game_info.num_rounds() - 1),
+            metric,
+            num_buckets,
+            edges,
+            board_samples: EhsBuckets::default_board_samples(),
+            opponent_samples: EhsBuckets::default_opponent_samples(),
+            edge_samples: EhsBuckets::default_edge_samples(),
+            seed: 0,
+            tables: OnceLock::new(),
+        }
+    }
+
+    fn default_board_samples() -> u32 {
+        64
+    }
+
+    fn default_opponent_samples() -> u32 {
+        64
+    }
+
+    fn default_edge_samples() -> u32 {
+        2000
+    }
+
+    fn tables(&self) -> &EhsTables {
+        self.tables.get_or_init(|| {
+            assert!(self.num_buckets > 0, "ehs buckets need at least one bucket");
+            let mut tables = EhsTables {
+                indexer: HandIndexer::new(self.num_suits, self.num_ranks, self.lowest_rank, &self.cards_per_round),
+                evaluator: Evaluator::new(),
+                deck: generate_deck(self.num_suits, self.num_ranks, self.lowest_rank).collect(),
+                edges: Vec::new(),
+                strengths: StrengthCache::new(),
+            };
+
+            tables.edges = match self.edges {
+                BucketEdges::Uniform => (1..self.num_buckets).map(|b| b as f64 / self.num_buckets as f64).collect(),
+                BucketEdges::Percentile => {
+                    let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+                    let num_cards = self.cards_per_round.iter().map(|&c| c as usize).sum();
+                    let mut deck = tables.deck.clone();
+                    let mut strengths: Vec<f64> = (0..self.edge_samples.max(1))
+                        .map(|_| {
+                            let (cards, _) = deck.partial_shuffle(&mut rng, num_cards);
+                            self.strength(&tables, tables.indexer.index(cards))
+                        })
+                        .collect();
+                    strengths.sort_by(f64::total_cmp);
+                    (1..self.num_buckets)
+                        .map(|b| strengths[b as usize * strengths.len() / self.num_buckets as usize])
+                        .collect()
+                },
+            };
+            debug!("EHS bucket edges {:?}", tables.edges);
+
+            tables
+        })
+    }
+
+    /// Strength of the hand with isomorphic index, computed on the canonical hand with a seed
+    /// derived from the index
+    fn strength(&self, tables: &EhsTables, index: u64) -> f64 {
+        tables.strengths.get_or_insert_with(index, || self.compute_strength(tables, index))
+    }
+
+    fn compute_strength(&self, tables: &EhsTables, index: u64) -> f64 {
+        let round = tables.indexer.num_rounds() - 1;
+        let cards = tables.indexer.unindex(round, index).unwrap();
+        let (hole_cards, board_cards) = cards.split_at(self.cards_per_round[0] as usize);
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed.wrapping_add(index));
+        let hand_strength = expected_hand_strength(&tables.evaluator, &tables.deck, hole_cards, board_cards, self.total_board_cards as usize, self.board_samples, self.opponent_samples, &mut rng);
+        match self.metric {
+            HandStrengthMetric::Ehs => hand_strength.ehs,
+            HandStrengthMetric::Ehs2 => hand_strength.ehs2,
+        }
+    }
+}
+
+#[typetag::serde]
+impl RoundBuckets for EhsBuckets {
+    fn get_bucket(&self, board_cards: &[Card], hole_cards: &[Card]) -> BucketId {
+        let tables = self.tables();
+        let num_board_cards = self.cards_per_round[1..].iter().map(|&c| c as usize).sum::<usize>();
+        let cards = [hole_cards, &board_cards[..num_board_cards]].concat();
+
+        let strength = self.strength(tables, tables.indexer.index(&cards));
+        tables.edges.partition_point(|&edge| edge <= strength) as BucketId
+    }
+
+    fn num_buckets(&self) -> u64 {
+        self.num_buckets as u64
+    }
+}
+
+/// Potential aware buckets looked up in a table written by the build-abstraction subcommand, see
+/// `clustering::build_emd_table`. The table has to be built for the same game and round.
+#[derive(Serialize, Deserialize)]
+pub struct EmdBuckets {
+    num_suits: u8,
+    num_ranks: u8,
+    #[serde(default)]
+    lowest_rank: u8,
+    round: u8,
+    cards_per_round: Vec<u8>,
+    table_path: PathBuf,
+    /// Source hash build-abstraction logs for the table. If set, a table built with other
+    /// parameters is rejected.
+    #[serde(default)]
+    source_hash: Option<u64>,
+    /// Loaded on first use
+    #[serde(skip)]
+    table: OnceLock<(BucketTable, HandIndexer)>,
+}
+
+impl EmdBuckets {
+    pub fn new(game_info: &GameInfo, round: u8, table_path: PathBuf, source_hash: Option<u64>) -> EmdBuckets {
+        EmdBuckets {
+            num_suits: game_info.num_suits(),
+            num_ranks: game_info.num_ranks(),
+            lowest_rank: game_info.lowest_rank(),
+            round,
+            cards_per_round: cards_per_round(game_info, round),
+            table_path,
+            source_hash,
+            table: OnceLock::new(),
+        }
+    }
+
+    /// Reads the table and checks it was built for this game and round
+    fn load(&self) -> Result<BucketTable, &'static str> {
+        let table = BucketTable::load(&self.table_path)?;
+        if table.num_suits != self.num_suits || table.num_ranks != self.num_ranks || table.lowest_rank != self.lowest_rank {
+            return Err("bucket table was built for another deck");
+        }
+        if table.round != self.round || table.cards_per_round != self.cards_per_round {
+            return Err("bucket table was built for another round");
+        }
+        if self.source_hash.is_some_and(|hash| hash != table.source_hash) {
+            return Err("bucket table was built with other parameters");
+        }
+        let indexer = table.indexer();
+        if table.len() as u64 != indexer.round_size(indexer.num_rounds() - 1) {
+            return Err("bucket table doesn't have a bucket for every hand");
+        }
+
+        Ok(table)
+    }
+
+    fn table(&self) -> &(BucketTable, HandIndexer) {
+        self.table.get_or_init(|| {
+            let table = self.load().unwrap_or_else(|e| panic!("failed to load bucket table {}: {}", self.table_path.display(), e));
+            let indexer = table.indexer();
+            (table, indexer)
+        })
+    }
+}
+
+#[typetag::serde]
+impl RoundBuckets for EmdBuckets {
+    fn get_bucket(&self, board_cards: &[Card], hole_cards: &[Card]) -> BucketId {
+        let (table, indexer) = self.table();
+        let num_board_cards = self.cards_per_round[1..].iter().map(|&c| c as usize).sum::<usize>();
+        table.get_bucket(indexer, &[hole_cards, &board_cards[..num_board_cards]].concat())
+    }
+
+    fn num_buckets(&self) -> u64 {
+        self.table().0.num_buckets as u64
+    }
+}
+
+/// Looks up the buckets of another abstraction in a table holding the bucket of every suit
+/// isomorphic hand of the round. The table is built and written to table_path the first time
+/// it's needed, or whenever the file on disk was made for a different game or abstraction. The
+/// wrapped abstraction has to give suit isomorphic hands the same bucket.
+#[derive(Serialize, Deserialize)]
+pub struct PrecomputedBuckets {
+    num_suits: u8,
+    num_ranks: u8,
+    #[serde(default)]
+    lowest_rank: u8,
+    round: u8,
+    cards_per_round: Vec<u8>,
+    table_path: PathBuf,
+    buckets: Box<dyn RoundBuckets>,
+    #[serde(skip)]
+    table: OnceLock<(BucketTable, HandIndexer)>,
+}
+
+impl PrecomputedBuckets {
+    pub fn new(game_info: &GameInfo, round: u8, table_path: PathBuf, buckets: Box<dyn RoundBuckets>) -> PrecomputedBuckets {
+        PrecomputedBuckets {
+            num_suits: game_info.num_suits(),
+            num_ranks: game_info.num_ranks(),
+            lowest_rank: game_info.lowest_rank(),
+            round,
+            cards_per_round: cards_per_round(game_info, round),
+            table_path,
+            buckets,
+            table: OnceLock::new(),
+        }
+    }
+
+    fn table(&self) -> &(BucketTable, HandIndexer) {
+        self.table.get_or_init(|| {
+            let table = match BucketTable::load(&self.table_path) {
+                Ok(table) if self.matches(&table) => table,
+                Ok(_) => {
+                    warn!("Bucket table {} was built for another game or abstraction, rebuilding it", self.table_path.display());
+                    self.build()
+                },
+                Err(e) => {
+                    info!("Building bucket table {}: {}", self.table_path.display(), e);
+                    self.build()
+                },
+            };
+            let indexer = table.indexer();
+            (table, indexer)
+        })
+    }
+
+    fn matches(&self, table: &BucketTable) -> bool {
+        table.num_suits == self.num_suits
+            && table.num_ranks == self.num_ranks
+            && table.lowest_rank == self.lowest_rank
+            && table.round == self.round
+            && table.cards_per_round == self.cards_per_round
+            && table.num_buckets as u64 == self.buckets.num_buckets()
+            && table.source_hash == config_hash(&self.buckets)
+    }
+
+    /// Buckets every canonical hand of the round with the wrapped abstraction and saves the table
+    fn build(&self) -> BucketTable {
+        let indexer = HandIndexer::new(self.num_suits, self.num_ranks, self.lowest_rank, &self.cards_per_round);
+        let last_round = indexer.num_rounds() - 1;
+        let num_hands = indexer.round_size(last_round) as usize;
+        let num_hole_cards = self.cards_per_round[0] as usize;
+        let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+        let mut buckets: Vec<BucketId> = vec![0; num_hands];
+        let chunk_size = num_hands.div_ceil(num_threads).max(1);
+        thread::scope(|s| {
+            for (chunk, chunk_buckets) in buckets.chunks_mut(chunk_size).enumerate() {
+                let indexer = &indexer;
+                s.spawn(move || {
+                    for (j, bucket) in chunk_buckets.iter_mut().enumerate() {
+                        let cards = indexer.unindex(last_round, (chunk * chunk_size + j) as u64).unwrap();
+                        let (hole_cards, board_cards) = cards.split_at(num_hole_cards);
+                        *bucket = self.buckets.get_bucket(board_cards, hole_cards);
+                    }
+                });
+            }
+        });
+
+        let num_buckets = u32::try_from(self.buckets.num_buckets()).expect("too many buckets to precompute");
+        let table = BucketTable::new(self.num_suits, self.num_ranks, self.lowest_rank, self.round, self.cards_per_round.clone(), num_buckets, config_hash(&self.buckets), buckets);
+        table.save(&self.table_path);
+        table
+    }
+}
+
+#[typetag::serde]
+impl RoundBuckets for PrecomputedBuckets {
+    fn get_bucket(&self, board_cards: &[Card], hole_cards: &[Card]) -> BucketId {
+        let (table, indexer) = self.table();
+        let num_board_cards = self.cards_per_round[1..].iter().map(|&c| c as usize).sum::<usize>();
+        table.get_bucket(indexer, &[hole_cards, &board_cards[..num_board_cards]].concat())
+    }
+
+    fn num_buckets(&self) -> u64 {
+        self.buckets.num_buckets()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use itertools::Itertools;
+
+    use std::env;
+
+    #[test]
+    fn precomputed_buckets_rebuild_for_another_abstraction() {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/holdem.json"));
+        let path = env::temp_dir().join(format!("ungar_precomputed_{}.bkt", std::process::id()));
+        let hands: Vec<Vec<Card>> = [["Ah", "Kh"], ["Ah", "Kd"], ["7c", "7d"], ["2s", "3s"]].iter()
+            .map(|h| h.iter().map(|c| c.parse().unwrap()).collect())
+            .collect();
+
+        // Both have 169 buckets, only the config hash tells their tables apart
+        let preflop = PreflopBuckets::new(&game_info);
+        let lossless = LosslessBuckets::new(&game_info, 0);
+        assert_eq!(preflop.num_buckets(), lossless.num_buckets());
+
+        let precomputed = PrecomputedBuckets::new(&game_info, 0, path.clone(), Box::new(PreflopBuckets::new(&game_info)));
+        for hand in &hands {
+            assert_eq!(precomputed.get_bucket(&[], hand), preflop.get_bucket(&[], hand));
+        }
+
+        let precomputed = PrecomputedBuckets::new(&game_info, 0, path.clone(), Box::new(LosslessBuckets::new(&game_info, 0)));
+        for hand in &hands {
+            assert_eq!(precomputed.get_bucket(&[], hand), lossless.get_bucket(&[], hand));
+        }
+        assert_ne!(preflop.get_bucket(&[], &hands[1]), lossless.get_bucket(&[], &hands[1]));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn short_deck_buckets_stay_in_range() {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/holdem_nl_short_deck.json"));
+        let deck: Vec<Card> = game_info.generate_deck().collect();
+        // Every ordered pair of the 36 cards, the 9x9 chart and the suit isomorphic hands
+        let round_buckets: Vec<(Box<dyn RoundBuckets>, u64)> = vec![
+            (Box::new(NoBuckets::new(&game_info, 0)), 36 * 36),
+            (Box::new(PreflopBuckets::new(&game_info)), 81),
+            (Box::new(LosslessBuckets::new(&game_info, 0)), 81),
+        ];
+        for (buckets, num_buckets) in &round_buckets {
+            assert_eq!(buckets.num_buckets(), *num_buckets);
+            for hand in deck.iter().copied().permutations(2) {
+                assert!((buckets.get_bucket(&[], &hand) as u64) < buckets.num_buckets());
+            }
+        }
+
+        let preflop = PreflopBuckets::new(&game_info);
+        let hand: Vec<Card> = ["As", "6s"].iter().map(|c| c.parse().unwrap()).collect();
+        assert_eq!(preflop.label(preflop.get_bucket(&[], &hand)), "A6s");
+    }
+
+    #[test]
+    fn emd_buckets_reject_tables_for_another_game_or_round() {
+        let holdem = GameInfo::load_game_info(Path::new("game_configs/holdem.json"));
+        let leduc = GameInfo::load_game_info(Path::new("game_configs/leduc.json"));
+        let path = env::temp_dir().join(format!("ungar_emd_{}.bkt", std::process::id()));
+        // 169 preflop hands in 10 buckets
+        let buckets: Vec<BucketId> = (0..169).map(|i| i % 10).collect();
+        BucketTable::new(4, 13, 0, 0, vec![2], 10, 42, buckets).save(&path);
+
+        let emd = EmdBuckets::new(&holdem, 0, path.clone(), Some(42));
+        assert!(emd.load().is_ok());
+        assert!((emd.get_bucket(&[], &cards("AsAh")) as u64) < emd.num_buckets());
+        assert_eq!(EmdBuckets::new(&leduc, 0, path.clone(), None).load().unwrap_err(), "bucket table was built for another deck");
+        assert_eq!(EmdBuckets::new(&holdem, 1, path.clone(), None).load().unwrap_err(), "bucket table was built for another round");
+        assert_eq!(EmdBuckets::new(&holdem, 0, path.clone(), Some(7)).load().unwrap_err(), "bucket table was built with other parameters");
+
+        BucketTable::new(4, 13, 0, 0, vec![2], 10, 42, vec![0; 100]).save(&path);
+        assert_eq!(EmdBuckets::new(&holdem, 0, path.clone(), None).load().unwrap_err(), "bucket table doesn't have a bucket for every hand");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn preflop_buckets_are_the_169_classes() {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/holdem.json"));
+        let preflop = PreflopBuckets::new(&game_info);
+        let label = |hand: &str| preflop.label(preflop.get_bucket(&[], &cards(hand)));
+        assert_eq!(label("AsKs"), "AKs");
+        assert_eq!(label("KhAh"), "AKs");
+        assert_eq!(label("AsKd"), "AKo");
+        assert_eq!(label("AsAd"), "AA");
+        assert_eq!(label("7c2d"), "72o");
+        assert_eq!(label("2c2d"), "22");
+
+        let hands: Vec<Vec<Card>> = generate_deck(4, 13, 0).combinations(2).collect();
+        assert_eq!(hands.len(), 1326);
+        let buckets: Vec<BucketId> = hands.iter().map(|hand| preflop.get_bucket(&[], hand)).unique().collect();
+        assert_eq!(buckets.len(), 169);
+        assert!(buckets.iter().all(|&b| (b as u64) < preflop.num_buckets()));
+        assert_eq!(buckets.iter().map(|&b| preflop.label(b)).unique().count(), 169);
+        // 13 pairs of 6 combos, 78 suited hands of 4 and 78 offsuit hands of 12
+        let counts = hands.iter().map(|hand| preflop.label(preflop.get_bucket(&[], hand))).counts();
+        assert_eq!(counts["AA"], 6);
+        assert_eq!(counts["AKs"], 4);
+        assert_eq!(counts["AKo"], 12);
+        assert_eq!(counts.values().filter(|&&n| n == 4).count(), 78);
+    }
+
+    fn cards(cards: &str) -> Vec<Card> {
+        crate::acpc::parse_cards(cards).unwrap()
+    }
+
+    /// Preflop EHS buckets of hold'em with fewer rollouts than the default to keep tests fast
+    fn preflop_ehs(metric: HandStrengthMetric, edges: BucketEdges) -> EhsBuckets {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/holdem.json"));
+        let mut buckets = EhsBuckets::new(&game_info, 0, metric, 10, edges);
+        buckets.board_samples = 16;
+        buckets.opponent_samples = 16;
+        buckets
+    }
+
+    #[test]
+    fn ehs_buckets_order_preflop_hands() {
+        let all_hands: Vec<Vec<Card>> = generate_deck(4, 13, 0).combinations(2).collect();
+        for metric in [HandStrengthMetric::Ehs, HandStrengthMetric::Ehs2] {
+            for edges in [BucketEdges::Uniform, BucketEdges::Percentile] {
+                let buckets = preflop_ehs(metric, edges);
+                let aces = buckets.get_bucket(&[], &cards("AsAh"));
+                let seven_deuce = buckets.get_bucket(&[], &cards("7c2d"));
+                match edges {
+                    // Aces win about 85% of the time and 72o about 35%, EHS^2 is lower still
+                    BucketEdges::Uniform => {
+                        assert!(aces >= 7, "{:?} aces in bucket {}", metric, aces);
+                        assert!(seven_deuce <= 3, "{:?} 72o in bucket {}", metric, seven_deuce);
+                    },
+                    BucketEdges::Percentile => {
+                        assert_eq!(aces, 9, "{:?} aces in bucket {}", metric, aces);
+                        assert!(seven_deuce <= 1, "{:?} 72o in bucket {}", metric, seven_deuce);
+                    },
+                }
+                // Suit isomorphic hands share a bucket
+                assert_eq!(buckets.get_bucket(&[], &cards("7h2s")), seven_deuce);
+                for hand in &all_hands {
+                    assert!(buckets.get_bucket(&[], hand) < 10);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ehs_buckets_are_deterministic_on_later_rounds() {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/holdem.json"));
+        let new_buckets = || {
+            let mut buckets = EhsBuckets::new(&game_info, 1, HandStrengthMetric::Ehs2, 20, BucketEdges::Uniform);
+            buckets.board_samples = 8;
+            buckets.opponent_samples = 8;
+            buckets
+        };
+        let (a, b) = (new_buckets(), new_buckets());
+        let board = cards("Ah7d2c9s4h");
+        for hand in ["AsAd", "KhQh", "7c2d", "3h5h"] {
+            let bucket = a.get_bucket(&board, &cards(hand));
+            assert!(bucket < 20);
+            assert_eq!(b.get_bucket(&board, &cards(hand)), bucket);
+        }
+        // A set of aces is far ahead of a gutshot with no pair
+        assert!(a.get_bucket(&board, &cards("AsAd")) > a.get_bucket(&board, &cards("3h5h")));
+    }
+
+    #[test]
+    fn strength_cache_stays_bounded() {
+        let cache = StrengthCache::new();
+        let num_shards = StrengthCache::NUM_SHARDS as u64;
+        // Every index lands in shard 0
+        let indices: Vec<u64> = (0..StrengthCache::MAX_SHARD_LEN as u64 + 10).map(|i| i * num_shards).collect();
+        for &index in &indices {
+            assert_eq!(cache.get_or_insert_with(index, || index as f64), index as f64);
+            assert!(cache.shards[0].lock().unwrap().len() <= StrengthCache::MAX_SHARD_LEN);
+        }
+
+        // The shard was cleared once full, so the first index is computed again
+        let mut recomputed = false;
+        assert_eq!(cache.get_or_insert_with(indices[0], || { recomputed = true; 0. }), 0.);
+        assert!(recomputed);
+        // The last one is still cached
+        assert_eq!(cache.get_or_insert_with(indices[indices.len() - 1], || panic!("not cached")), indices[indices.len() - 1] as f64);
     }
 }
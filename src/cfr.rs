@@ -1,332 +1,992 @@
 use super::{
     abstract_game::AbstractGame,
-    game::{Action, PlayerId, MAX_PLAYERS},
-    strategy::{ Strategy, Regrets },
-    node::NodeId,
+    accumulator::{Accumulator, AccumulatorType},
+    game::{Action, GameState, PlayerId},
+    strategy::{ InfosetTable, Strategy, Regrets },
+    node::{NodeId, Nodes},
+    card_abstraction::BucketId,
+    action_abstraction::ActionAbstraction,
+    card_abstraction::CardAbstraction,
+    game::GameInfo,
 };
 
-use std::{collections::BTreeMap, io::BufWriter};
-use std::cmp::max;
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter, Write};
+use std::cmp::min;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
 use std::fs;
 use std::path::Path;
 use rand::Rng;
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
 
 use log::info;
 
 use serde::{Serialize, Deserialize};
 
-use poker::{Card, Evaluator};
+use poker::Card;
+
+/// Which CFR variant `CFREngine::train` runs
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Algorithm {
+    /// Sampled MCCFR with linear discounting, the sampling scheme is set separately
+    Mccfr,
+    /// Full tree CFR over every deal, every player is updated from the same iteration's strategy
+    Vanilla,
+    /// Full tree CFR with regret matching+, alternating updates and linear averaging
+    CfrPlus,
+    /// Full tree discounted CFR, positive regrets are scaled by t^alpha/(t^alpha + 1), negative
+    /// regrets by t^beta/(t^beta + 1) and the average strategy by (t/(t + 1))^gamma. Updates
+    /// alternate between players like in the DCFR paper.
+    Discounted { alpha: f64, beta: f64, gamma: f64 },
+}
+
+/// How MCCFR samples the tree on each traversal
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Sampling {
+    /// External sampling with negative regret pruning once past prune_threshold, as in Pluribus
+    ExternalPruned,
+    /// External sampling, explores every traverser action and samples everyone else's
+    External,
+    /// Samples a single trajectory, the traverser picks uniformly at random with probability
+    /// epsilon and regrets are importance weighted
+    Outcome { epsilon: f64 },
+    /// Only the deal is sampled, every action of every player is explored
+    Chance,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CFRConfig {
+    #[serde(default = "CFRConfig::default_algorithm")]
+    pub algorithm: Algorithm,
+    /// Sampling scheme used by the Mccfr algorithm
+    #[serde(default = "CFRConfig::default_sampling")]
+    pub sampling: Sampling,
     rounds_update_average_strategy: u8,
-    payout_amp: i32,
+    /// Type regrets and average strategy weights are stored as
+    #[serde(default = "CFRConfig::default_accumulator")]
+    pub accumulator: AccumulatorType,
+    /// Regrets are never allowed to drop below this. If unset only integer regrets get a floor,
+    /// the accumulator's `DEFAULT_REGRET_FLOOR`.
+    #[serde(default)]
+    pub regret_floor: Option<f64>,
+    /// Number of MCCFR iterations to run
+    #[serde(default = "CFRConfig::default_ticks")]
+    pub ticks: u32,
+    /// Average strategy is updated every strategy_interval iterations
+    #[serde(default = "CFRConfig::default_strategy_interval")]
+    pub strategy_interval: u32,
+    /// Iteration after which negative regret pruning can happen
+    #[serde(default = "CFRConfig::default_prune_threshold")]
+    pub prune_threshold: u32,
+    /// Iteration after which linear CFR discounting stops
+    #[serde(default = "CFRConfig::default_lcfr_threshold")]
+    pub lcfr_threshold: u32,
+    /// Regrets and average strategy are discounted every discount_interval iterations
+    #[serde(default = "CFRConfig::default_discount_interval")]
+    pub discount_interval: u32,
+    /// Probability of doing a traversal without pruning once past prune_threshold
+    #[serde(default = "CFRConfig::default_prune_probability")]
+    pub prune_probability: f32,
+    /// Actions with regret at or below this are skipped when pruning
+    #[serde(default = "CFRConfig::default_regret_prune_threshold")]
+    pub regret_prune_threshold: f64,
+    /// A checkpoint is written every checkpoint_interval iterations if set
+    #[serde(default)]
+    pub checkpoint_interval: Option<u32>,
+    /// Number of threads traversing in parallel
+    #[serde(default = "CFRConfig::default_num_threads")]
+    pub num_threads: usize,
+    /// Seed of the deals and sampling, a random one gets picked and logged if not set. Runs
+    /// with the same seed are identical as long as they use a single thread.
+    #[serde(default)]
+    pub seed: Option<u64>,
 }
 
 impl CFRConfig  {
-    pub fn new(rounds_update_average_strategy: u8, payout_amp: i32) -> CFRConfig {
+    pub fn new(rounds_update_average_strategy: u8) -> CFRConfig {
         CFRConfig {
+            algorithm: CFRConfig::default_algorithm(),
+            sampling: CFRConfig::default_sampling(),
             rounds_update_average_strategy,
-            payout_amp,
+            accumulator: CFRConfig::default_accumulator(),
+            regret_floor: None,
+            ticks: CFRConfig::default_ticks(),
+            strategy_interval: CFRConfig::default_strategy_interval(),
+            prune_threshold: CFRConfig::default_prune_threshold(),
+            lcfr_threshold: CFRConfig::default_lcfr_threshold(),
+            discount_interval: CFRConfig::default_discount_interval(),
+            prune_probability: CFRConfig::default_prune_probability(),
+            regret_prune_threshold: CFRConfig::default_regret_prune_threshold(),
+            checkpoint_interval: None,
+            num_threads: CFRConfig::default_num_threads(),
+            seed: None,
         }
     }
 
     pub fn from_config(path: &Path) -> CFRConfig {
-        let cfr_config = serde_json::from_str(&fs::read_to_string(path).expect("failed to read cfr config")).expect("failed to deserialize cfr config");
+        let cfr_config: CFRConfig = serde_json::from_str(&fs::read_to_string(path).expect("failed to read cfr config")).expect("failed to deserialize cfr config");
+        cfr_config.validate().expect("invalid cfr config");
         cfr_config
     }
+
+    /// Checks that the hyperparameters make sense together
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.strategy_interval == 0 {
+            return Err("strategy_interval must be positive");
+        }
+
+        if self.discount_interval == 0 {
+            return Err("discount_interval must be positive");
+        }
+
+        if !(0. ..=1.).contains(&self.prune_probability) {
+            return Err("prune_probability must be between 0 and 1");
+        }
+
+        if self.regret_prune_threshold.is_nan() || self.regret_prune_threshold > 0. {
+            return Err("regret_prune_threshold must not be positive");
+        }
+
+        if let Some(regret_floor) = self.regret_floor {
+            if regret_floor.is_nan() || regret_floor > 0. {
+                return Err("regret_floor must not be positive");
+            }
+        }
+
+        if self.checkpoint_interval == Some(0) {
+            return Err("checkpoint_interval must be positive");
+        }
+
+        if self.num_threads == 0 {
+            return Err("num_threads must be positive");
+        }
+
+        if let Sampling::Outcome { epsilon } = self.sampling {
+            if !(epsilon > 0. && epsilon <= 1.) {
+                return Err("outcome sampling epsilon must be in (0, 1]");
+            }
+        }
+
+        if let Algorithm::Discounted { alpha, beta, gamma } = self.algorithm {
+            if !alpha.is_finite() || !beta.is_finite() || !gamma.is_finite() || gamma < 0. {
+                return Err("discounted cfr parameters must be finite and gamma non negative");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn default_algorithm() -> Algorithm {
+        Algorithm::Mccfr
+    }
+
+    fn default_sampling() -> Sampling {
+        Sampling::ExternalPruned
+    }
+
+    fn default_accumulator() -> AccumulatorType {
+        AccumulatorType::F64
+    }
+
+    fn default_ticks() -> u32 {
+        150000
+    }
+
+    fn default_strategy_interval() -> u32 {
+        20
+    }
+
+    fn default_prune_threshold() -> u32 {
+        400
+    }
+
+    fn default_lcfr_threshold() -> u32 {
+        100000
+    }
+
+    fn default_discount_interval() -> u32 {
+        2500
+    }
+
+    fn default_prune_probability() -> f32 {
+        0.05
+    }
+
+    fn default_regret_prune_threshold() -> f64 {
+        -300000000.
+    }
+
+    fn default_num_threads() -> usize {
+        1
+    }
+}
+
+/// Regret and average strategy changes of one infoset from a full tree pass
+struct InfosetUpdate {
+    node_id: NodeId,
+    bucket_id: BucketId,
+    regret_delta: Vec<f64>,
+    strategy_delta: Vec<f64>,
+}
+
+/// What stays fixed over one MCCFR traversal
+struct Traversal<'a> {
+    /// Shuffled deck the traversal's cards are dealt from
+    deck: &'a [Card],
+    player: PlayerId,
+    sampling: Sampling,
+    /// Skip traverser actions with regret below regret_prune_threshold
+    prune: bool,
+}
+
+/// Everything needed to pick a training run back up, written by `CFREngine::save_checkpoint`
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "T: Accumulator")]
+struct Checkpoint<T: Accumulator> {
+    /// Next iteration to run
+    iteration: u32,
+    config: CFRConfig,
+    regrets: Regrets<T>,
+    average_strategy: Regrets<T>,
+    nodes: Nodes,
+    rng: ChaCha8Rng,
+}
+
+/// Leading fields of a `Checkpoint`, enough to find out which accumulator type it was written with
+#[derive(Deserialize)]
+struct CheckpointHeader {
+    #[allow(dead_code)]
+    iteration: u32,
+    config: CFRConfig,
+}
+
+impl CFRConfig {
+    /// Reads the config a checkpoint was written with
+    pub fn from_checkpoint(path: &Path) -> CFRConfig {
+        let mut r = BufReader::new(fs::File::open(path).expect("failed to open checkpoint"));
+        let header: CheckpointHeader = bincode::deserialize_from(&mut r).expect("failed to deserialize checkpoint");
+        header.config
+    }
 }
 
-pub struct CFREngine {
+pub struct CFREngine<T: Accumulator> {
     abstract_game: AbstractGame,
-    average_strategy: Strategy,
-    regrets: Regrets,
-    evaluator: Evaluator,
+    average_strategy: InfosetTable<T>,
+    regrets: InfosetTable<T>,
     config: CFRConfig,
+    /// Next iteration to run
+    iteration: u32,
+    rng: ChaCha8Rng,
 }
 
-impl CFREngine {
-    pub fn new(abstract_game: AbstractGame, config: CFRConfig) -> CFREngine {
+impl<T: Accumulator> CFREngine<T> {
+    pub fn new(abstract_game: AbstractGame, config: CFRConfig) -> CFREngine<T> {
+        let seed = config.seed.unwrap_or_else(rand::random);
+        info!("Training with seed {}", seed);
+
         CFREngine {
             abstract_game,
-            average_strategy: Strategy::new(),
-            regrets: Regrets::new(), 
-            evaluator: Evaluator::new(),
+            average_strategy: InfosetTable::new(),
+            regrets: InfosetTable::new(),
             config,
+            iteration: 0,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    /// Restores an engine from a checkpoint, the game and abstractions are not stored in the
+    /// checkpoint so they have to be the same ones the run was started with
+    pub fn from_checkpoint(path: &Path, game_info: GameInfo, action_abstraction: ActionAbstraction, card_abstraction: CardAbstraction) -> CFREngine<T> {
+        let mut r = BufReader::new(fs::File::open(path).expect("failed to open checkpoint"));
+        let checkpoint: Checkpoint<T> = bincode::deserialize_from(&mut r).expect("failed to deserialize checkpoint");
+        info!("Resuming from checkpoint {} at iteration {}", path.display(), checkpoint.iteration);
+
+        CFREngine {
+            abstract_game: AbstractGame::from_nodes(game_info, checkpoint.nodes, action_abstraction, card_abstraction),
+            average_strategy: InfosetTable::from_map(checkpoint.average_strategy),
+            regrets: InfosetTable::from_map(checkpoint.regrets),
+            config: checkpoint.config,
+            iteration: checkpoint.iteration,
+            rng: checkpoint.rng,
         }
     }
 
+    /// Writes a checkpoint of the current run, goes through a temporary file so a crash while
+    /// writing doesn't clobber the previous checkpoint
+    pub fn save_checkpoint(&self, path: &Path) {
+        #[derive(Serialize)]
+        struct CheckpointRef<'a, T: Accumulator> {
+            iteration: u32,
+            config: &'a CFRConfig,
+            regrets: &'a Regrets<T>,
+            average_strategy: &'a Regrets<T>,
+            nodes: &'a Nodes,
+            rng: &'a ChaCha8Rng,
+        }
+
+        let checkpoint = CheckpointRef {
+            iteration: self.iteration,
+            config: &self.config,
+            regrets: &self.regrets.to_map(),
+            average_strategy: &self.average_strategy.to_map(),
+            nodes: &self.abstract_game.nodes,
+            rng: &self.rng,
+        };
+
+        let tmp_path = path.with_extension("tmp");
+        let mut f = BufWriter::new(fs::File::create(&tmp_path).expect("failed to create checkpoint"));
+        bincode::serialize_into(&mut f, &checkpoint).expect("failed to serialize checkpoint");
+        f.flush().expect("failed to write checkpoint");
+        drop(f);
+        fs::rename(&tmp_path, path).expect("failed to move checkpoint into place");
+        info!("Saved checkpoint {} at iteration {}", path.display(), self.iteration);
+    }
+
+    pub fn config_mut(&mut self) -> &mut CFRConfig {
+        &mut self.config
+    }
+
+    pub fn average_strategy(&self) -> Strategy {
+        Strategy::from_weights(self.average_strategy.to_map())
+    }
+
     pub fn print_average_strategy(&self) {
-        println!("{:?}", self.average_strategy);
+        println!("{:?}", self.average_strategy());
     }
 
     pub fn save_average_strategy(&self, path: &Path) {
         let mut f = BufWriter::new(fs::File::create(path).unwrap());
-        bincode::serialize_into(&mut f, &self.average_strategy).unwrap();
+        bincode::serialize_into(&mut f, &self.average_strategy()).unwrap();
     }
 
     pub fn save_nodes(&self, path: &Path) {
-        self.abstract_game.nodes.save(path);
+        self.abstract_game.save_nodes(path);
     }
 
     pub fn print_regrets(&self) {
-        println!("{:?}", self.regrets);
+        println!("{:?}", self.regrets.to_map());
     }
 
-    pub fn mccfr_p(&mut self, ticks: u32, strategy_interval: u32, prune_threshold: u32, lcfr_threshold: u32, discount_interval: u32) {
-        let num_players = self.abstract_game.game_info.num_players();
+    /// Trains with the algorithm chosen in the config
+    pub fn train(&mut self, checkpoint_path: Option<&Path>) {
+        match self.config.algorithm {
+            Algorithm::Mccfr => self.mccfr(checkpoint_path),
+            _ => self.full_tree_cfr(checkpoint_path),
+        }
+    }
 
-        for t in 0..ticks {
-            info!("Iteration {:?}", t);
-            for i in 0..num_players {
-                if t % strategy_interval == 0 {
-                    let (hole_cards, board_cards) = self.abstract_game.game_info.deal_hole_cards_and_board_cards();
-                    self.update_strategy(self.abstract_game.nodes.get_root_node_id(), &board_cards, self.abstract_game.game_info.total_board_cards(0) as usize, &hole_cards, i);
+    /// Runs MCCFR with the configured sampling scheme until config.ticks iterations have been done, starting from the
+    /// engine's current iteration, and writes checkpoints to checkpoint_path if given. With more
+    /// than one thread iterations are handed out to workers that traverse asynchronously on the
+    /// shared tables, they only synchronize for discounting and checkpoints.
+    pub fn mccfr(&mut self, checkpoint_path: Option<&Path>) {
+        let CFRConfig { ticks, lcfr_threshold, discount_interval, checkpoint_interval, num_threads, .. } = self.config;
+
+        while self.iteration < ticks {
+            let start = self.iteration;
+            let end = self.next_sync_iteration();
+
+            if num_threads <= 1 {
+                let mut rng = self.rng.clone();
+                for t in start..end {
+                    self.mccfr_iteration(t, &mut rng);
                 }
-                if t > prune_threshold {
-                    let mut rng = rand::thread_rng();
-                    if rng.gen::<f32>() < 0.05 {
-                        let (hole_cards, board_cards) = self.abstract_game.game_info.deal_hole_cards_and_board_cards();
-                        self.traverse_mccrfr(self.abstract_game.nodes.get_root_node_id(), &board_cards, self.abstract_game.game_info.total_board_cards(0) as usize, &hole_cards, i);
-                    } else {
-                        let (hole_cards, board_cards) = self.abstract_game.game_info.deal_hole_cards_and_board_cards();
-                        self.traverse_mccrfr_p(self.abstract_game.nodes.get_root_node_id(), &board_cards, self.abstract_game.game_info.total_board_cards(0) as usize, &hole_cards, i);
+                self.rng = rng;
+            } else {
+                let next_iteration = AtomicU32::new(start);
+                let seeds: Vec<u64> = (0..num_threads).map(|_| self.rng.gen()).collect();
+                let engine = &*self;
+
+                thread::scope(|s| {
+                    for seed in seeds {
+                        let next_iteration = &next_iteration;
+                        s.spawn(move || {
+                            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                            loop {
+                                let t = next_iteration.fetch_add(1, Ordering::Relaxed);
+                                if t >= end {
+                                    break;
+                                }
+                                engine.mccfr_iteration(t, &mut rng);
+                            }
+                        });
                     }
-                } else {
-                        let (hole_cards, board_cards) = self.abstract_game.game_info.deal_hole_cards_and_board_cards();
-                        self.traverse_mccrfr(self.abstract_game.nodes.get_root_node_id(), &board_cards, self.abstract_game.game_info.total_board_cards(0) as usize, &hole_cards, i);
-                }
+                });
             }
 
-            if t < lcfr_threshold && t % discount_interval == 0 {
-                let d: f32 = (t as f32 / discount_interval as f32) / ((t as f32 / discount_interval as f32) + 1.);
+            self.iteration = end;
 
-                //CHECK: this type of multiplication tends to give overflow errors!
-                for regrets in self.regrets.values_mut() {
-                    for v in regrets.values_mut() {
-                        *v = ((*v as f32) * d).round() as i32;
-                    }
-                }
-                for strategy in self.average_strategy.0.values_mut() {
-                    for v in strategy.values_mut() {
-                        *v = ((*v as f32) * d).round() as i32;
-                    }
+            // Discounting happens after iteration t where t is a multiple of discount_interval
+            let t = end - 1;
+            if t < lcfr_threshold && t.is_multiple_of(discount_interval) {
+                let d = (t as f64 / discount_interval as f64) / ((t as f64 / discount_interval as f64) + 1.);
+
+                // Goes through f64 so integer accumulators can't overflow while scaling
+                self.regrets.for_each_value_mut(|v| *v = v.scale(d));
+                self.average_strategy.for_each_value_mut(|v| *v = v.scale(d));
+            }
+
+            if let (Some(interval), Some(path)) = (checkpoint_interval, checkpoint_path) {
+                if self.iteration.is_multiple_of(interval) {
+                    self.save_checkpoint(path);
                 }
             }
         }
-    }
 
-    pub fn calculate_strategy(regrets: &BTreeMap<Action, i32>) -> BTreeMap<Action, f32> {
-        let mut sum = 0;
-        for v in regrets.values() {
-            if *v > 0 {
-                sum += *v;
+        // Final checkpoint so the run can be extended later with more ticks
+        if let Some(path) = checkpoint_path {
+            if checkpoint_interval.is_none_or(|interval| !self.iteration.is_multiple_of(interval)) {
+                self.save_checkpoint(path);
             }
         }
+    }
 
-        let mut strategy: BTreeMap<Action, f32> = BTreeMap::new();
-        let length = regrets.len();
-        for (k, v) in regrets.iter() {
-            if sum > 0 {
-                strategy.insert(*k, max(*v, 0) as f32 / sum as f32);
-            } else {
-                strategy.insert(*k, 1. / length as f32);
+    /// Iteration after which training has to stop to discount or write a checkpoint
+    fn next_sync_iteration(&self) -> u32 {
+        let CFRConfig { ticks, lcfr_threshold, discount_interval, checkpoint_interval, .. } = self.config;
+        let mut end = ticks;
+
+        // Discount after the next iteration that is a multiple of discount_interval
+        let next_discount = self.iteration.next_multiple_of(discount_interval);
+        if next_discount < lcfr_threshold {
+            end = min(end, next_discount + 1);
+        }
+
+        if let Some(interval) = checkpoint_interval {
+            end = min(end, (self.iteration + 1).next_multiple_of(interval));
+        }
+
+        end
+    }
+
+    fn mccfr_iteration(&self, t: u32, rng: &mut ChaCha8Rng) {
+        let CFRConfig { sampling, strategy_interval, prune_threshold, prune_probability, .. } = self.config;
+        let root = self.abstract_game.get_root_node_id();
+
+        info!("Iteration {:?}", t);
+        for i in 0..self.abstract_game.game_info.num_players() {
+            if t.is_multiple_of(strategy_interval) {
+                let deck = self.abstract_game.game_info.generate_shuffled_deck(rng);
+                self.update_strategy(root, &self.root_state(&deck), &deck, i, rng);
             }
+            let deck = self.abstract_game.game_info.generate_shuffled_deck(rng);
+            let prune = sampling == Sampling::ExternalPruned && t > prune_threshold && rng.gen::<f32>() >= prune_probability;
+            let ctx = Traversal {
+                deck: &deck,
+                player: i,
+                sampling,
+                prune,
+            };
+            self.traverse(&ctx, root, &self.root_state(&deck), 1., 1., 1., rng);
         }
+    }
 
-        strategy
+    /// State of the root node tracking the cards dealt from deck
+    fn root_state(&self, deck: &[Card]) -> GameState {
+        let game_info = &self.abstract_game.game_info;
+        self.abstract_game.get_state(self.abstract_game.get_root_node_id()).clone()
+            .with_cards()
+            .deal_from(game_info, deck)
+            .expect("failed to deal the root state")
     }
 
-    fn sample_strategy(sigma: &BTreeMap<Action, f32>) -> Action {
-        let mut rng = rand::thread_rng();
-        *sigma.iter().collect::<Vec<(&Action, &f32)>>().choose_weighted(&mut rng, |item| item.1).unwrap().0
+    /// state after action with the cards due next dealt from deck
+    fn child_state(&self, state: &GameState, action: Action, deck: &[Card]) -> GameState {
+        let game_info = &self.abstract_game.game_info;
+        state.apply_action_no_cards(game_info, action)
+            .and_then(|s| s.deal_from(game_info, deck))
+            .expect("failed to apply action")
     }
 
-    pub fn update_strategy(&mut self, node_id: NodeId, board_cards: &Vec<Card>, board_cards_i: usize, hole_cards: &[Vec<Card>; MAX_PLAYERS], player: PlayerId) {
-        let current_node = self.abstract_game.nodes.get_node(node_id).unwrap();
+    /// Regret matching, sigma[a] is the probability of the a-th action
+    pub fn calculate_strategy(regrets: &[T]) -> Vec<f64> {
+        // Summed in f64 so large integer regrets can't overflow
+        let sum: f64 = regrets.iter().map(|v| v.to_f64().max(0.)).sum();
+
+        if sum > 0. {
+            regrets.iter().map(|v| v.to_f64().max(0.) / sum).collect()
+        } else {
+            vec![1. / regrets.len() as f64; regrets.len()]
+        }
+    }
+
+    /// Index of an action sampled from sigma
+    fn sample_strategy(sigma: &[f64], rng: &mut impl Rng) -> usize {
+        (0..sigma.len()).collect::<Vec<usize>>().choose_weighted(rng, |&a| sigma[a]).copied().unwrap_or(0)
+    }
+
+    /// Converts a regret back to the accumulator type, clamping it at regret_floor or the
+    /// accumulator's default floor. Integer accumulators saturate instead of overflowing.
+    fn to_regret(&self, regret: f64) -> T {
+        T::from_f64(match self.config.regret_floor.or(T::DEFAULT_REGRET_FLOOR) {
+            Some(floor) => regret.max(floor),
+            None => regret,
+        })
+    }
+
+    fn add_regret(&self, regret: &mut T, delta: f64) {
+        *regret = self.to_regret(regret.to_f64() + delta);
+    }
+
+    /// Adds one to a sampled action of player at every node the sampled opponent actions lead
+    /// to. card_state is node_id's state tracking the cards dealt from deck.
+    pub fn update_strategy(&self, node_id: NodeId, card_state: &GameState, deck: &[Card], player: PlayerId, rng: &mut ChaCha8Rng) {
+        let state = self.abstract_game.get_state(node_id);
         debug!("Updating strategy of node {node_id}");
 
         // CHECK: Doesn't generate average strategy past first betting round
-        if current_node.state.is_finished() || current_node.state.has_folded(player) || current_node.state.current_round() > self.config.rounds_update_average_strategy {
+        if state.is_finished() || state.has_folded(player) || state.current_round() > self.config.rounds_update_average_strategy {
             return;
-        } else if current_node.state.current_player().unwrap() == player {
-            let bucket_id = self.abstract_game.get_bucket(current_node.state.current_round(), &board_cards, &hole_cards[player as usize]);
-            let regrets = self.regrets.entry((node_id, bucket_id))
-                .or_insert_with(|| {
-                    let mut regrets_map: BTreeMap<Action, i32> = BTreeMap::new();
-                    for a in self.abstract_game.get_actions(&current_node.state) {
-                        regrets_map.insert(a, 0); // inserts with uniform distribution
-                    }
-                    regrets_map
-                });
-            let sigma = CFREngine::calculate_strategy(regrets);
-            let action = CFREngine::sample_strategy(&sigma);
+        }
 
-            // Add one to action counter
-            self.average_strategy.0.entry((node_id, bucket_id))
-                .and_modify(|s| { let _ = *s.entry(action).and_modify(|x| *x += 1).or_insert(0); })
-                .or_insert_with(|| {
-                    let mut action_map: BTreeMap<Action, i32> = BTreeMap::new();
+        if state.current_player().unwrap() == player {
+            let bucket_id = self.abstract_game.get_state_bucket(card_state, player);
+            let actions = self.abstract_game.get_actions(state);
+            let sigma = self.regrets.read((node_id, bucket_id), actions.len(), Self::calculate_strategy);
+            let a = Self::sample_strategy(&sigma, rng);
+            let action = actions[a];
 
-                    for a in self.abstract_game.get_actions(&current_node.state) {
-                        action_map.insert(a, 0); // inserts with uniform distribution
-                    }
-                    action_map.insert(action, 1);
-                    action_map
-                });
+            // Add one to action counter
+            self.average_strategy.update((node_id, bucket_id), actions.len(), |s| s[a] = s[a].add(1.));
 
-            let mut child_board_cards_i = board_cards_i;
-            let child_node_id = self.abstract_game.apply_action_to_node(node_id, &mut child_board_cards_i, action);
-            self.update_strategy(child_node_id, board_cards, child_board_cards_i, hole_cards, player);
+            let child_node_id = self.abstract_game.get_child(node_id, action).unwrap();
+            self.update_strategy(child_node_id, &self.child_state(card_state, action, deck), deck, player, rng);
 
         } else {
-            let actions = self.abstract_game.get_actions(&current_node.state);
+            let actions = self.abstract_game.get_actions(state);
             for action in actions {
-                let mut child_board_cards_i = board_cards_i;
-                let child_node_id = self.abstract_game.apply_action_to_node(node_id, &mut child_board_cards_i, action);
-                self.update_strategy(child_node_id, board_cards, child_board_cards_i, hole_cards, player);
+                let child_node_id = self.abstract_game.get_child(node_id, action).unwrap();
+                self.update_strategy(child_node_id, &self.child_state(card_state, action, deck), deck, player, rng);
             }
         }
 
     }
 
-    pub fn traverse_mccrfr(&mut self, node_id: NodeId, board_cards: &Vec<Card>, board_cards_i: usize, hole_cards: &[Vec<Card>; MAX_PLAYERS], player: PlayerId) -> i32 {
-        let current_node = self.abstract_game.nodes.get_node(node_id).unwrap();
+    /// Shared traversal for every sampling scheme, returns the traverser's (sampled) value at
+    /// node_id and the probability of everyone playing from node_id to the sampled terminal node,
+    /// which only outcome sampling uses. reach and opponent_reach are the traverser's and everyone
+    /// else's probability of reaching the node, sample_prob is the probability the traversal
+    /// sampled the path to it. card_state is node_id's state tracking the cards dealt from
+    /// ctx.deck.
+    #[allow(clippy::too_many_arguments)]
+    fn traverse(&self, ctx: &Traversal, node_id: NodeId, card_state: &GameState, reach: f64, opponent_reach: f64, sample_prob: f64, rng: &mut ChaCha8Rng) -> (f64, f64) {
+        let state = self.abstract_game.get_state(node_id);
+        let player = ctx.player;
+
+        debug!("traverse at node {node_id}");
+
+        //CHECK: for a folded traverser the paper keeps traversing(traverse_mccfr(h*0, P_i)), but
+        //returning the payout right away makes more sense
+        if state.is_finished() || state.has_folded(player) {
+            let payout = card_state.get_dealt_payout(&self.abstract_game.game_info, player).expect("failed to get payout");
+            return (payout as f64 / sample_prob, 1.);
+        }
 
-        debug!("traverse_mccfr at node {node_id}");
+        let current_player = state.current_player().unwrap();
+        let bucket_id = self.abstract_game.get_state_bucket(card_state, current_player);
+        let actions = self.abstract_game.get_actions(state);
+        let prune = ctx.prune && current_player == player;
+        // Actions skipped by pruning are only worked out when pruning, so the usual case allocates
+        // nothing but sigma
+        let (sigma, pruned) = self.regrets.read((node_id, bucket_id), actions.len(), |r| {
+            let pruned: Vec<bool> = if prune {
+                r.iter().map(|v| v.to_f64() <= self.config.regret_prune_threshold).collect()
+            } else {
+                Vec::new()
+            };
+            (Self::calculate_strategy(r), pruned)
+        });
+
+        let explore_all = match ctx.sampling {
+            Sampling::Chance => true,
+            Sampling::External | Sampling::ExternalPruned => current_player == player,
+            Sampling::Outcome { .. } => false,
+        };
+
+        if explore_all {
+            let mut v = 0.;
+            let mut action_values: Vec<Option<f64>> = vec![None; actions.len()];
 
-        if current_node.state.is_finished() {
-            return current_node.state.get_payout(&self.abstract_game.game_info, &self.evaluator, &board_cards, &hole_cards, player) * self.config.payout_amp;
-        } else if current_node.state.has_folded(player) {
-            //CHECK: this is what they do in paper return traverse_mccfr(h*0, P_i), but I think
-            //this makes more sense
-            return current_node.state.get_payout(&self.abstract_game.game_info, &self.evaluator, &board_cards, &hole_cards, player) * self.config.payout_amp;
-        } else if current_node.state.current_player().unwrap() == player {
-            let bucket_id = self.abstract_game.get_bucket(current_node.state.current_round(), &board_cards, &hole_cards[player as usize]);
-            let regrets = self.regrets.entry((node_id, bucket_id))
-                .or_insert_with(|| {
-                    let mut regrets_map: BTreeMap<Action, i32> = BTreeMap::new();
-                    for a in self.abstract_game.get_actions(&current_node.state) {
-                        regrets_map.insert(a, 0); // inserts with uniform distribution
-                    }
-                    regrets_map
-                });
-            let sigma = CFREngine::calculate_strategy(regrets);
+            for (a, action) in actions.iter().enumerate() {
+                if prune && pruned[a] {
+                    continue;
+                }
 
-            let mut v = 0.;
-            let mut value_map: BTreeMap<Action, i32> = BTreeMap::new();
-
-            let actions = self.abstract_game.get_actions(&current_node.state);
-            for action in &actions {
-                let mut child_board_cards_i = board_cards_i;
-                let child_node_id = self.abstract_game.apply_action_to_node(node_id, &mut child_board_cards_i, *action);
-                value_map.insert(*action, self.traverse_mccrfr(child_node_id, board_cards, child_board_cards_i, hole_cards, player));
-                v += *sigma.get(action).unwrap_or(&0.) * (*value_map.get(action).unwrap() as f32);
+                let (child_reach, child_opponent_reach) = if current_player == player {
+                    (reach * sigma[a], opponent_reach)
+                } else {
+                    (reach, opponent_reach * sigma[a])
+                };
+
+                let child_node_id = self.abstract_game.get_child(node_id, *action).unwrap();
+                let child_state = self.child_state(card_state, *action, ctx.deck);
+                let (value, _) = self.traverse(ctx, child_node_id, &child_state, child_reach, child_opponent_reach, sample_prob, rng);
+                action_values[a] = Some(value);
+                v += sigma[a] * value;
             }
-            let v = v.round() as i32;
 
-            self.regrets.entry((node_id, bucket_id))
-                .and_modify(|r| {
-                    for action in actions {
-                        r.entry(action).and_modify(|regret| {
-                            *regret += *value_map.get(&action).unwrap_or(&0) - v;
-                        });
+            if current_player == player {
+                // opponent_reach stays 1 for external sampling since opponent actions are sampled
+                self.regrets.update((node_id, bucket_id), actions.len(), |r| {
+                    for (regret, value) in r.iter_mut().zip(&action_values) {
+                        if let Some(value) = value {
+                            self.add_regret(regret, opponent_reach * (value - v));
+                        }
                     }
                 });
+            }
+
+            return (v, 1.);
+        }
 
-            return v;
+        // Only one action gets sampled, the traverser explores in outcome sampling
+        let sample_sigma: Vec<f64> = match ctx.sampling {
+            Sampling::Outcome { epsilon } if current_player == player => sigma.iter()
+                .map(|p| epsilon / actions.len() as f64 + (1. - epsilon) * p)
+                .collect(),
+            _ => sigma.clone(),
+        };
+        let a = Self::sample_strategy(&sample_sigma, rng);
+
+        let child_node_id = self.abstract_game.get_child(node_id, actions[a]).unwrap();
+        let child_state = self.child_state(card_state, actions[a], ctx.deck);
+
+        if !matches!(ctx.sampling, Sampling::Outcome { .. }) {
+            // External sampling of an opponent action, sampling cancels out the opponent's reach
+            return self.traverse(ctx, child_node_id, &child_state, reach, opponent_reach, sample_prob, rng);
+        }
+
+        let (child_reach, child_opponent_reach) = if current_player == player {
+            (reach * sigma[a], opponent_reach)
         } else {
-            let bucket_id = self.abstract_game.get_bucket(current_node.state.current_round(), &board_cards, &hole_cards[player as usize]);
+            (reach, opponent_reach * sigma[a])
+        };
+        let (u, tail) = self.traverse(ctx, child_node_id, &child_state, child_reach, child_opponent_reach, sample_prob * sample_sigma[a], rng);
+
+        if current_player == player {
+            let w = u * opponent_reach;
+            self.regrets.update((node_id, bucket_id), actions.len(), |r| {
+                for (b, regret) in r.iter_mut().enumerate() {
+                    let delta = if b == a {
+                        w * tail * (1. - sigma[a])
+                    } else {
+                        -w * tail * sigma[a]
+                    };
+                    self.add_regret(regret, delta);
+                }
+            });
+        }
 
-            let regrets = self.regrets.entry((node_id, bucket_id))
-                .or_insert_with(|| {
-                    let mut regrets_map: BTreeMap<Action, i32> = BTreeMap::new();
-                    for a in self.abstract_game.get_actions(&current_node.state) {
-                        regrets_map.insert(a, 0); // inserts with uniform distribution
-                    }
-                    regrets_map
-                });
-            let sigma = CFREngine::calculate_strategy(regrets);
-            let action = CFREngine::sample_strategy(&sigma);
+        (u, tail * sigma[a])
+    }
 
-            let mut child_board_cards_i = board_cards_i;
-            let child_node_id = self.abstract_game.apply_action_to_node(node_id, &mut child_board_cards_i, action);
-            return self.traverse_mccrfr(child_node_id, board_cards, child_board_cards_i, hole_cards, player);
+    /// Runs a deterministic full tree variant of CFR until config.ticks iterations are done, every
+    /// deal is enumerated on each iteration so this is only feasible for small games.
+    pub fn full_tree_cfr(&mut self, checkpoint_path: Option<&Path>) {
+        let deals = self.abstract_game.game_info.enumerate_deals();
+        info!("Running {:?} over {} deals", self.config.algorithm, deals.len());
+
+        let root = self.abstract_game.get_root_node_id();
+        let root_states: Vec<(&[Card], GameState)> = deals.iter()
+            .map(|deck| (&deck[..], self.root_state(deck)))
+            .collect();
+        let ones = vec![1.; deals.len()];
+
+        while self.iteration < self.config.ticks {
+            info!("Iteration {:?}", self.iteration);
+
+            let num_players = self.abstract_game.game_info.num_players();
+            if self.config.algorithm == Algorithm::Vanilla {
+                // Simultaneous updates, every player's values are worked out before any regret
+                // changes
+                let mut updates = Vec::new();
+                for player in 0..num_players {
+                    self.full_tree_values(root, &root_states, &ones, &ones, player, &mut updates);
+                }
+                self.apply_full_tree_updates(updates);
+            } else {
+                // Alternating updates, each player's regrets see the others' updated strategy
+                for player in 0..num_players {
+                    let mut updates = Vec::new();
+                    self.full_tree_values(root, &root_states, &ones, &ones, player, &mut updates);
+                    self.apply_full_tree_updates(updates);
+                }
+            }
+
+            self.iteration += 1;
+
+            if let (Some(interval), Some(path)) = (self.config.checkpoint_interval, checkpoint_path) {
+                if self.iteration.is_multiple_of(interval) {
+                    self.save_checkpoint(path);
+                }
+            }
+        }
+
+        if let Some(path) = checkpoint_path {
+            if self.config.checkpoint_interval.is_none_or(|interval| !self.iteration.is_multiple_of(interval)) {
+                self.save_checkpoint(path);
+            }
         }
     }
 
+    /// Returns player's value for each of deals at node_id and adds the changes to player's
+    /// regrets and average strategy to updates. Each deal is a deck and node_id's state tracking
+    /// the cards dealt from it. reach[i] is player's probability of reaching the node with
+    /// deals[i] and opponent_reach[i] the probability of everyone else.
+    fn full_tree_values(&self, node_id: NodeId, deals: &[(&[Card], GameState)], reach: &[f64], opponent_reach: &[f64], player: PlayerId, updates: &mut Vec<InfosetUpdate>) -> Vec<f64> {
+        let state = self.abstract_game.get_state(node_id);
+
+        if state.is_finished() || state.has_folded(player) {
+            return deals.iter()
+                .map(|(_, card_state)| card_state.get_dealt_payout(&self.abstract_game.game_info, player).expect("failed to get payout") as f64)
+                .collect();
+        }
 
-    pub fn traverse_mccrfr_p(&mut self, node_id: NodeId, board_cards: &Vec<Card>, board_cards_i: usize, hole_cards: &[Vec<Card>; MAX_PLAYERS], player: PlayerId) -> i32 {
-        let current_node = self.abstract_game.nodes.get_node(node_id).unwrap();
+        let current_player = state.current_player().unwrap();
+        let actions = self.abstract_game.get_actions(state);
 
-        debug!("traverse_mccfr_p at node {node_id}");
+        let buckets: Vec<BucketId> = deals.iter()
+            .map(|(_, card_state)| self.abstract_game.get_state_bucket(card_state, current_player))
+            .collect();
 
-        if current_node.state.is_finished() {
-            return current_node.state.get_payout(&self.abstract_game.game_info, &self.evaluator, &board_cards, &hole_cards, player) * self.config.payout_amp;
-        } else if current_node.state.has_folded(player) {
-            //CHECK: this is what they do in paper return traverse_mccfr(h*0, P_i), but I think
-            //this makes more sense
-            return current_node.state.get_payout(&self.abstract_game.game_info, &self.evaluator, &board_cards, &hole_cards, player) * self.config.payout_amp;
-        } else if current_node.state.current_player().unwrap() == player {
-            let bucket_id = self.abstract_game.get_bucket(current_node.state.current_round(), &board_cards, &hole_cards[player as usize]);
-            let regrets = self.regrets.entry((node_id, bucket_id))
-                .or_insert_with(|| {
-                    let mut regrets_map: BTreeMap<Action, i32> = BTreeMap::new();
-                    for a in self.abstract_game.get_actions(&current_node.state) {
-                        regrets_map.insert(a, 0); // inserts with uniform distribution
-                    }
-                    regrets_map
-                });
-            let sigma = CFREngine::calculate_strategy(regrets);
+        let mut sigmas: BTreeMap<BucketId, Vec<f64>> = BTreeMap::new();
+        for &bucket_id in &buckets {
+            sigmas.entry(bucket_id).or_insert_with(|| {
+                self.regrets.read((node_id, bucket_id), actions.len(), Self::calculate_strategy)
+            });
+        }
 
-            let mut v = 0.;
-            let mut value_map: BTreeMap<Action, i32> = BTreeMap::new();
-
-            // CHECK: way to do this without clone and the borrow rules working
-            let regrets = regrets.clone();
-
-            let actions = self.abstract_game.get_actions(&current_node.state);
-            for action in &actions {
-                if *regrets.get(action).unwrap_or(&0) > -300000000 {
-                    let mut child_board_cards_i = board_cards_i;
-                    let child_node_id = self.abstract_game.apply_action_to_node(node_id, &mut child_board_cards_i, *action);
-                    value_map.insert(*action, self.traverse_mccrfr_p(child_node_id, board_cards, child_board_cards_i, hole_cards, player));
-                    v += *sigma.get(action).unwrap_or(&0.) * (*value_map.get(action).unwrap() as f32);
+        let mut values = vec![0.; deals.len()];
+        let mut action_values = Vec::with_capacity(actions.len());
+
+        for (a, action) in actions.iter().enumerate() {
+            let child_node_id = self.abstract_game.get_child(node_id, *action).unwrap();
+
+            let mut child_deals = Vec::with_capacity(deals.len());
+            let mut child_reach = Vec::with_capacity(deals.len());
+            let mut child_opponent_reach = Vec::with_capacity(deals.len());
+            let mut indices = Vec::with_capacity(deals.len());
+            for (i, &(deck, ref card_state)) in deals.iter().enumerate() {
+                let p = sigmas[&buckets[i]][a];
+                let (r, o) = if current_player == player {
+                    (reach[i] * p, opponent_reach[i])
+                } else {
+                    (reach[i], opponent_reach[i] * p)
+                };
+
+                // Deals nobody reaches can't change regrets or the average strategy
+                if r > 0. || o > 0. {
+                    child_deals.push((deck, self.child_state(card_state, *action, deck)));
+                    child_reach.push(r);
+                    child_opponent_reach.push(o);
+                    indices.push(i);
                 }
             }
 
-            let v = v.round() as i32;
+            let mut child_values = vec![0.; deals.len()];
+            if !child_deals.is_empty() {
+                let v = self.full_tree_values(child_node_id, &child_deals, &child_reach, &child_opponent_reach, player, updates);
+                for (j, &i) in indices.iter().enumerate() {
+                    child_values[i] = v[j];
+                }
+            }
 
-            self.regrets.entry((node_id, bucket_id))
-                .and_modify(|r| {
-                    for action in actions {
-                        r.entry(action).and_modify(|regret| {
-                            if let Some(x) = value_map.get(&action) {
-                                *regret += x - v;
-                            }
-                        });
-                    }
-                });
-            
-            return v;
-        } else {
-            let bucket_id = self.abstract_game.get_bucket(current_node.state.current_round(), &board_cards, &hole_cards[player as usize]);
+            for (i, value) in values.iter_mut().enumerate() {
+                *value += sigmas[&buckets[i]][a] * child_values[i];
+            }
+            action_values.push(child_values);
+        }
 
-            let regrets = self.regrets.entry((node_id, bucket_id))
-                .or_insert_with(|| {
-                    let mut regrets_map: BTreeMap<Action, i32> = BTreeMap::new();
-                    for a in self.abstract_game.get_actions(&current_node.state) {
-                        regrets_map.insert(a, 0); // inserts with uniform distribution
-                    }
-                    regrets_map
-                });
-            let sigma = CFREngine::calculate_strategy(regrets);
-            let action = CFREngine::sample_strategy(&sigma);
+        if current_player != player {
+            return values;
+        }
+
+        // Sum regrets and strategy contributions over deals that share an infoset so each one is
+        // stored once
+        let mut regret_deltas: BTreeMap<BucketId, Vec<f64>> = BTreeMap::new();
+        let mut strategy_deltas: BTreeMap<BucketId, Vec<f64>> = BTreeMap::new();
+        for (i, &bucket_id) in buckets.iter().enumerate() {
+            let sigma = &sigmas[&bucket_id];
+            let regret_delta = regret_deltas.entry(bucket_id).or_insert_with(|| vec![0.; actions.len()]);
+            let strategy_delta = strategy_deltas.entry(bucket_id).or_insert_with(|| vec![0.; actions.len()]);
+            for a in 0..actions.len() {
+                regret_delta[a] += opponent_reach[i] * (action_values[a][i] - values[i]);
+                strategy_delta[a] += reach[i] * sigma[a];
+            }
+        }
+
+        for (bucket_id, regret_delta) in regret_deltas {
+            updates.push(InfosetUpdate {
+                node_id,
+                bucket_id,
+                regret_delta,
+                strategy_delta: strategy_deltas.remove(&bucket_id).unwrap(),
+            });
+        }
+
+        values
+    }
 
-            let mut child_board_cards_i = board_cards_i;
-            let child_node_id = self.abstract_game.apply_action_to_node(node_id, &mut child_board_cards_i, action);
-            return self.traverse_mccrfr_p(child_node_id, board_cards, child_board_cards_i, hole_cards, player);
+    /// Adds the changes of a full tree pass to the regrets and average strategy
+    fn apply_full_tree_updates(&self, updates: Vec<InfosetUpdate>) {
+        let t = (self.iteration + 1) as f64;
+        let algorithm = self.config.algorithm;
+
+        for InfosetUpdate { node_id, bucket_id, regret_delta, strategy_delta } in updates {
+            let num_actions = regret_delta.len();
+            self.regrets.update((node_id, bucket_id), num_actions, |r| {
+                for (a, regret) in r.iter_mut().enumerate() {
+                    let current = regret.to_f64();
+                    let discounted = match algorithm {
+                        Algorithm::Discounted { alpha, .. } if current > 0. => current * t.powf(alpha) / (t.powf(alpha) + 1.),
+                        Algorithm::Discounted { beta, .. } => current * t.powf(beta) / (t.powf(beta) + 1.),
+                        _ => current,
+                    };
+                    let updated = discounted + regret_delta[a];
+                    *regret = match algorithm {
+                        Algorithm::CfrPlus => T::from_f64(updated.max(0.)),
+                        _ => self.to_regret(updated),
+                    };
+                }
+            });
+
+            self.average_strategy.update((node_id, bucket_id), num_actions, |s| {
+                for (a, count) in s.iter_mut().enumerate() {
+                    *count = match algorithm {
+                        Algorithm::CfrPlus => count.add(t * strategy_delta[a]),
+                        Algorithm::Discounted { gamma, .. } => count.scale((t / (t + 1.)).powf(gamma)).add(strategy_delta[a]),
+                        _ => count.add(strategy_delta[a]),
+                    };
+                }
+            });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        best_response::exploitability,
+        game::GameInfo,
+    };
+
+    fn kuhn() -> AbstractGame {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/kuhn.json"));
+        let state = GameState::new(&game_info, 0);
+        let action_abstraction = ActionAbstraction::from_config(Path::new("game_configs/kuhn_action_abstraction.json"));
+        let card_abstraction = CardAbstraction::from_config(Path::new("game_configs/kuhn_card_abstraction.json"));
+        AbstractGame::new(game_info, state, action_abstraction, card_abstraction)
+    }
+
+    fn config(algorithm: Algorithm, sampling: Sampling, ticks: u32) -> CFRConfig {
+        let mut config = CFRConfig::new(0);
+        config.algorithm = algorithm;
+        config.sampling = sampling;
+        config.ticks = ticks;
+        config.seed = Some(7);
+        config
+    }
+
+    /// Trains Kuhn with config and returns the engine
+    fn train(config: CFRConfig) -> CFREngine<f64> {
+        let mut engine = CFREngine::new(kuhn(), config);
+        engine.train(None);
+        engine
+    }
+
+    fn kuhn_exploitability(engine: &CFREngine<f64>) -> f64 {
+        exploitability(&engine.abstract_game, &engine.average_strategy()).exploitability
+    }
+
+    #[test]
+    fn full_tree_algorithms_converge_on_kuhn() {
+        // Simultaneous updates make vanilla CFR the slowest
+        let algorithms = [
+            (Algorithm::Vanilla, 0.02),
+            (Algorithm::CfrPlus, 0.001),
+            (Algorithm::Discounted { alpha: 1.5, beta: 0., gamma: 2. }, 0.001),
+        ];
+        for (algorithm, threshold) in algorithms {
+            let engine = train(config(algorithm, Sampling::External, 1000));
+            let exploitability = kuhn_exploitability(&engine);
+            assert!(exploitability < threshold, "{:?} exploitability {}", algorithm, exploitability);
+        }
+    }
+
+    #[test]
+    fn sampling_schemes_converge_on_kuhn() {
+        // Pruning only kicks in with regrets far below anything Kuhn reaches by default
+        let mut pruned = config(Algorithm::Mccfr, Sampling::ExternalPruned, 10000);
+        pruned.prune_threshold = 100;
+        pruned.regret_prune_threshold = -20.;
+        let configs = [
+            (pruned, 0.1),
+            (config(Algorithm::Mccfr, Sampling::External, 10000), 0.04),
+            (config(Algorithm::Mccfr, Sampling::Chance, 10000), 0.04),
+        ];
+        for (config, threshold) in configs {
+            let sampling = config.sampling;
+            let engine = train(config);
+            let exploitability = kuhn_exploitability(&engine);
+            assert!(exploitability < threshold, "{:?} exploitability {}", sampling, exploitability);
+        }
+    }
+
+    #[test]
+    fn outcome_sampling_keeps_improving() {
+        let mut engine = train(config(Algorithm::Mccfr, Sampling::Outcome { epsilon: 0.6 }, 1000));
+        let early = kuhn_exploitability(&engine);
+        engine.config_mut().ticks = 10000;
+        engine.train(None);
+        let late = kuhn_exploitability(&engine);
+        // Regrets that weren't weighted by the sampling probability would settle on the wrong
+        // strategy instead of closing in on an equilibrium
+        assert!(late < early / 2., "exploitability went from {} to {}", early, late);
+        assert!(late < 0.06, "exploitability {}", late);
+    }
+
+    #[test]
+    fn only_integer_regrets_get_a_floor_by_default() {
+        let engine: CFREngine<f64> = CFREngine::new(kuhn(), config(Algorithm::Mccfr, Sampling::External, 1));
+        assert_eq!(engine.to_regret(-1e9), -1e9);
+        let engine: CFREngine<i32> = CFREngine::new(kuhn(), config(Algorithm::Mccfr, Sampling::External, 1));
+        assert_eq!(engine.to_regret(-1e9), -310000000);
+
+        let mut floored = config(Algorithm::Mccfr, Sampling::External, 1);
+        floored.regret_floor = Some(-100.);
+        let engine: CFREngine<f32> = CFREngine::new(kuhn(), floored);
+        assert_eq!(engine.to_regret(-1e9), -100.);
+    }
+
+    #[test]
+    fn threads_share_the_tables() {
+        let mut config = config(Algorithm::Mccfr, Sampling::External, 20000);
+        config.num_threads = 2;
+        let engine = train(config);
+        assert_eq!(engine.iteration, 20000);
+        // Uniform play is exploitable for 7/12 a game
+        let exploitability = kuhn_exploitability(&engine);
+        assert!(exploitability < 0.05, "exploitability {}", exploitability);
+    }
+
+    #[test]
+    fn single_threaded_training_is_reproducible_from_the_seed() {
+        let seeded = |seed| {
+            let mut config = config(Algorithm::Mccfr, Sampling::External, 1000);
+            config.num_threads = 1;
+            config.seed = Some(seed);
+            config
+        };
+        let first = train(seeded(7));
+        let second = train(seeded(7));
+        assert_eq!(first.regrets.to_map(), second.regrets.to_map());
+        assert_eq!(first.average_strategy.to_map(), second.average_strategy.to_map());
+
+        let other = train(seeded(8));
+        assert_ne!(first.regrets.to_map(), other.regrets.to_map());
+    }
+}
@@ -0,0 +1,174 @@
+/*
+* Client for the ACPC dealer protocol, plays a trained strategy in matches run by an ACPC dealer
+*/
+
+use super::{
+    abstract_game::AbstractGame,
+    acpc::{format_action, parse_match_state},
+    action_translation::{abstract_to_real, follow_actions, TranslationMethod},
+    game::Action,
+    strategy::Strategy,
+};
+
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+const PROTOCOL_VERSION: &str = "VERSION:2.0.0";
+
+/// Connects to a dealer at host:port and plays until it closes the connection
+pub fn connect(abstract_game: &AbstractGame, strategy: &Strategy, translation: TranslationMethod, seed: u64, host: &str, port: u16) -> io::Result<()> {
+    let stream = TcpStream::connect((host, port))?;
+    stream.set_nodelay(true)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    run_client(abstract_game, strategy, translation, seed, reader, stream)
+}
+
+/// Speaks the dealer protocol over reader and writer: answers every MATCHSTATE where it's our
+/// turn with the strategy's action and ignores the rest. The same seed and messages always get
+/// the same replies.
+pub fn run_client<R: BufRead, W: Write>(abstract_game: &AbstractGame, strategy: &Strategy, translation: TranslationMethod, seed: u64, reader: R, mut writer: W) -> io::Result<()> {
+    let game_info = &abstract_game.game_info;
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    write!(writer, "{}\r\n", PROTOCOL_VERSION)?;
+    writer.flush()?;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end();
+        // Comments and GUI messages
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        let match_state = match parse_match_state(game_info, line) {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("Ignoring dealer message {:?}: {}", line, e);
+                continue;
+            },
+        };
+        // Randomized translation has to map the hand's history the same way every message
+        let mut translation_rng = ChaCha8Rng::seed_from_u64(seed ^ match_state.hand_number as u64);
+        let (state, node_id) = follow_actions(translation, abstract_game, match_state.hand_number, &match_state.actions, &mut translation_rng)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let state = state.with_dealt_cards(match_state.hole_cards, match_state.board_cards);
+        if state.is_finished() || state.current_player() != Ok(match_state.position) {
+            continue;
+        }
+
+        let action = match node_id {
+            Some(node_id) => {
+                let abstract_state = abstract_game.get_state(node_id);
+                let bucket_id = abstract_game.get_bucket(abstract_state.current_round(), state.board_cards(), state.hole_cards(match_state.position));
+                let abstract_action = strategy.sample(abstract_game, node_id, bucket_id, &mut rng);
+                abstract_to_real(game_info, &state, abstract_state, abstract_action)
+            },
+            None => {
+                debug!("Hand {} left the abstract game, calling", match_state.hand_number);
+                Action::Call
+            },
+        };
+        // The dealer doesn't allow folding when there's nothing to call
+        let position = match_state.position;
+        let action = match action {
+            Action::Fold if state.max_spent() == state.player_spent(position) => Action::Call,
+            _ => action,
+        };
+
+        write!(writer, "{}:{}\r\n", line, format_action(game_info, action))?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        action_abstraction::ActionAbstraction,
+        card_abstraction::{CardAbstraction, NoBuckets, RoundBuckets},
+        game::{GameInfo, GameState},
+    };
+
+    use std::io::Cursor;
+    use std::path::Path;
+
+    fn abstract_game(name: &str) -> AbstractGame {
+        let game_info = GameInfo::load_game_info(&Path::new("game_configs").join(format!("{}.json", name)));
+        let state = GameState::new(&game_info, 0);
+        let action_abstraction = ActionAbstraction::from_config(&Path::new("game_configs").join(format!("{}_action_abstraction.json", name)));
+        let card_abstraction = CardAbstraction::new((0..game_info.num_rounds())
+            .map(|round| Box::new(NoBuckets::new(&game_info, round)) as Box<dyn RoundBuckets>)
+            .collect());
+        AbstractGame::new(game_info, state, action_abstraction, card_abstraction)
+    }
+
+    /// Makes strategy always take action in the spot the MATCHSTATE line describes
+    fn pin(abstract_game: &AbstractGame, strategy: &mut Strategy, line: &str, action: Action) {
+        let match_state = parse_match_state(&abstract_game.game_info, line).unwrap();
+        let (state, node_id) = follow_actions(TranslationMethod::Nearest, abstract_game, match_state.hand_number, &match_state.actions, &mut ChaCha8Rng::seed_from_u64(0)).unwrap();
+        let state = state.with_dealt_cards(match_state.hole_cards, match_state.board_cards);
+        let node_id = node_id.unwrap();
+        let abstract_state = abstract_game.get_state(node_id);
+        let bucket_id = abstract_game.get_bucket(abstract_state.current_round(), state.board_cards(), state.hole_cards(match_state.position));
+        let weights = abstract_game.get_actions(abstract_state).into_iter()
+            .map(|a| if a == action { 1. } else { 0. })
+            .collect();
+        strategy.0.insert((node_id, bucket_id), weights);
+    }
+
+    fn replies(abstract_game: &AbstractGame, strategy: &Strategy, messages: &[&str]) -> Vec<String> {
+        let input = messages.iter().map(|m| format!("{}\r\n", m)).collect::<String>();
+        let mut output = Vec::new();
+        run_client(abstract_game, strategy, TranslationMethod::Nearest, 0, Cursor::new(input), &mut output).unwrap();
+        String::from_utf8(output).unwrap().lines().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn answers_its_turns_in_a_limit_game() {
+        let abstract_game = abstract_game("leduc");
+        let mut strategy = Strategy::new();
+        pin(&abstract_game, &mut strategy, "MATCHSTATE:0:3::4c|", Action::Raise(2));
+        pin(&abstract_game, &mut strategy, "MATCHSTATE:0:3:rc/:4c|/3h", Action::Call);
+
+        let messages = [
+            "# comments are skipped",
+            "MATCHSTATE:0:3::4c|",
+            "MATCHSTATE:0:3:r:4c|",
+            "MATCHSTATE:0:3:rc/:4c|/3h",
+            "MATCHSTATE:0:3:rc/c:4c|/3h",
+            "MATCHSTATE:0:3:rc/cc:4c|2c/3h",
+        ];
+        assert_eq!(replies(&abstract_game, &strategy, &messages), [
+            PROTOCOL_VERSION,
+            "MATCHSTATE:0:3::4c|:r",
+            "MATCHSTATE:0:3:rc/:4c|/3h:c",
+        ]);
+    }
+
+    #[test]
+    fn answers_no_limit_raises_with_amounts() {
+        let abstract_game = abstract_game("holdem_nl");
+        let mut strategy = Strategy::new();
+        // The big blind faces a raise to 300 and makes it pot, 900
+        pin(&abstract_game, &mut strategy, "MATCHSTATE:0:0:r300:AsAh|", Action::Raise(900));
+        pin(&abstract_game, &mut strategy, "MATCHSTATE:0:0:r300r900c/:AsAh|/Kd7c2h", Action::Call);
+
+        let messages = [
+            "MATCHSTATE:0:0::AsAh|",
+            "MATCHSTATE:0:0:r300:AsAh|",
+            "MATCHSTATE:0:0:r300r900:AsAh|",
+            "MATCHSTATE:0:0:r300r900c/:AsAh|/Kd7c2h",
+        ];
+        assert_eq!(replies(&abstract_game, &strategy, &messages), [
+            PROTOCOL_VERSION,
+            "MATCHSTATE:0:0:r300:AsAh|:r900",
+            "MATCHSTATE:0:0:r300r900c/:AsAh|/Kd7c2h:c",
+        ]);
+    }
+}
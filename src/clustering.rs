@@ -0,0 +1,301 @@
+/*
+* Offline potential aware card abstraction. Every suit isomorphic hand of a round gets a
+* histogram of its equity after the next round's cards are dealt, hands are then clustered with
+* k-means under the earth mover's distance between histograms.
+*/
+
+use super::{
+    bucket_table::{config_hash, BucketTable},
+    card_abstraction::{cards_per_round, BucketId},
+    equity::{expected_hand_strength, hand_strength},
+    game::GameInfo,
+    hand_indexer::HandIndexer,
+};
+
+use poker::{Card, Evaluator};
+
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use serde::Serialize;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Parameters for building an EMD bucket table, their hash is the table's source hash
+#[derive(Debug, Serialize)]
+pub struct EmdAbstractionConfig {
+    pub round: u8,
+    pub num_buckets: u32,
+    /// Equity bins of each hand's histogram
+    pub num_bins: usize,
+    /// Next round deals sampled per hand
+    pub next_round_samples: u32,
+    /// Board rollouts and opponent hands used to estimate each sampled equity
+    pub rollout_samples: u32,
+    pub opponent_samples: u32,
+    /// K-means iterations
+    pub iterations: u32,
+    pub seed: u64,
+    /// Doesn't change the buckets so it's left out of the source hash
+    #[serde(skip)]
+    pub num_threads: usize,
+}
+
+/// Earth mover's distance between two histograms over the same ordered bins, in bins
+pub fn emd(a: &[f32], b: &[f32]) -> f32 {
+    let mut carried = 0.;
+    let mut distance = 0.;
+    for (x, y) in a.iter().zip(b) {
+        carried += x - y;
+        distance += f32::abs(carried);
+    }
+    distance
+}
+
+/// Clusters the histograms stored back to back in points into k clusters, returns each point's
+/// cluster. Clusters are numbered by increasing mean bin so higher buckets are stronger hands.
+pub fn kmeans<R: Rng>(points: &[f32], num_bins: usize, k: usize, iterations: u32, num_threads: usize, rng: &mut R) -> Vec<BucketId> {
+    let num_points = points.len() / num_bins;
+    assert!(num_points > 0 && k > 0, "kmeans needs points and clusters");
+    let point = |i: usize| &points[i * num_bins..(i + 1) * num_bins];
+
+    // k-means++ seeding
+    let mut centers: Vec<f32> = point(rng.gen_range(0..num_points)).to_vec();
+    let mut distances: Vec<f32> = (0..num_points).map(|i| emd(point(i), &centers[..num_bins])).collect();
+    while centers.len() / num_bins < k {
+        let total: f64 = distances.iter().map(|&d| d as f64 * d as f64).sum();
+        let next = if total > 0. {
+            let mut target = rng.gen::<f64>() * total;
+            distances.iter()
+                .position(|&d| {
+                    target -= d as f64 * d as f64;
+                    target <= 0.
+                })
+                .unwrap_or(num_points - 1)
+        } else {
+            rng.gen_range(0..num_points)
+        };
+
+        let center = point(next).to_vec();
+        for (i, d) in distances.iter_mut().enumerate() {
+            *d = d.min(emd(point(i), &center));
+        }
+        centers.extend(center);
+    }
+
+    // The 1D EMD between two histograms is the L1 distance between their CDFs
+    let cdfs: Vec<f32> = points.chunks(num_bins)
+        .flat_map(|p| p.iter().scan(0., |total, &x| {
+            *total += x;
+            Some(*total)
+        }))
+        .collect();
+
+    let mut assignments = vec![0; num_points];
+    let mut column = Vec::new();
+    for iteration in 0..iterations {
+        let changed = assign(points, num_bins, &centers, &mut assignments, num_threads);
+        info!("K-means iteration {}: {} points changed cluster", iteration, changed);
+
+        // The histogram minimizing the summed EMD to a cluster's members is the one whose CDF is
+        // the per bin median of theirs, medians of CDFs are still increasing so it is a histogram
+        let mut members: Vec<Vec<usize>> = vec![Vec::new(); k];
+        for (i, &c) in assignments.iter().enumerate() {
+            members[c as usize].push(i);
+        }
+        for (c, members) in members.iter().enumerate() {
+            let center = &mut centers[c * num_bins..(c + 1) * num_bins];
+            if members.is_empty() {
+                // Restart empty clusters on a random point
+                center.copy_from_slice(point(rng.gen_range(0..num_points)));
+                continue;
+            }
+
+            let mut previous = 0.;
+            for (b, x) in center.iter_mut().enumerate() {
+                column.clear();
+                column.extend(members.iter().map(|&i| cdfs[i * num_bins + b]));
+                let mid = column.len() / 2;
+                let median = *column.select_nth_unstable_by(mid, f32::total_cmp).1;
+                *x = median - previous;
+                previous = median;
+            }
+        }
+
+        if changed == 0 && iteration > 0 {
+            break;
+        }
+    }
+    assign(points, num_bins, &centers, &mut assignments, num_threads);
+
+    let mean_bin = |c: usize| -> f32 {
+        centers[c * num_bins..(c + 1) * num_bins].iter().enumerate().map(|(b, &p)| b as f32 * p).sum()
+    };
+    let mut order: Vec<usize> = (0..k).collect();
+    order.sort_by(|&a, &b| mean_bin(a).total_cmp(&mean_bin(b)));
+    let mut relabel = vec![0; k];
+    for (bucket, &c) in order.iter().enumerate() {
+        relabel[c] = bucket as BucketId;
+    }
+
+    assignments.iter().map(|&c| relabel[c as usize]).collect()
+}
+
+/// Moves every point to its closest center, returns how many points changed cluster
+fn assign(points: &[f32], num_bins: usize, centers: &[f32], assignments: &mut [BucketId], num_threads: usize) -> usize {
+    let chunk_size = assignments.len().div_ceil(num_threads.max(1));
+    let changed = AtomicUsize::new(0);
+
+    thread::scope(|s| {
+        for (chunk, assignment_chunk) in assignments.chunks_mut(chunk_size).enumerate() {
+            let changed = &changed;
+            s.spawn(move || {
+                let mut chunk_changed = 0;
+                for (j, assignment) in assignment_chunk.iter_mut().enumerate() {
+                    let i = chunk * chunk_size + j;
+                    let p = &points[i * num_bins..(i + 1) * num_bins];
+                    let closest = centers.chunks(num_bins)
+                        .map(|center| emd(p, center))
+                        .enumerate()
+                        .min_by(|a, b| a.1.total_cmp(&b.1))
+                        .unwrap()
+                        .0 as BucketId;
+                    if closest != *assignment {
+                        chunk_changed += 1;
+                        *assignment = closest;
+                    }
+                }
+                changed.fetch_add(chunk_changed, Ordering::Relaxed);
+            });
+        }
+    });
+
+    changed.into_inner()
+}
+
+/// Clusters every suit isomorphic hand of config.round, hands of the last round are histograms
+/// with all their mass in their hand strength's bin
+pub fn build_emd_table(game_info: &GameInfo, config: &EmdAbstractionConfig) -> BucketTable {
+    assert!(config.round < game_info.num_rounds(), "round is out of range");
+    assert!(config.num_buckets > 0 && config.num_bins > 0, "need at least one bucket and one bin");
+    let cards_per_round = cards_per_round(game_info, config.round);
+    let indexer = HandIndexer::new(game_info.num_suits(), game_info.num_ranks(), game_info.lowest_rank(), &cards_per_round);
+    let num_hands = indexer.round_size(indexer.num_rounds() - 1) as usize;
+    info!("Building histograms for {} hands", num_hands);
+
+    let evaluator = Evaluator::new();
+    let deck: Vec<Card> = game_info.generate_deck().collect();
+    let total_board_cards = game_info.total_board_cards(game_info.num_rounds() - 1) as usize;
+    let num_hole_cards = cards_per_round[0] as usize;
+    let next_round_cards = if config.round + 1 < game_info.num_rounds() {
+        Some(game_info.num_board_cards(config.round + 1) as usize)
+    } else {
+        None
+    };
+
+    let mut points = vec![0f32; num_hands * config.num_bins];
+    let chunk_size = num_hands.div_ceil(config.num_threads.max(1));
+    thread::scope(|s| {
+        for (chunk, chunk_points) in points.chunks_mut(chunk_size * config.num_bins).enumerate() {
+            let (indexer, evaluator, deck) = (&indexer, &evaluator, &deck);
+            s.spawn(move || {
+                for (j, histogram) in chunk_points.chunks_mut(config.num_bins).enumerate() {
+                    let index = (chunk * chunk_size + j) as u64;
+                    let mut rng = ChaCha8Rng::seed_from_u64(config.seed.wrapping_add(index));
+                    let cards = indexer.unindex(indexer.num_rounds() - 1, index).unwrap();
+                    let (hole_cards, board_cards) = cards.split_at(num_hole_cards);
+                    let bin = |equity: f64| ((equity * config.num_bins as f64) as usize).min(config.num_bins - 1);
+
+                    match next_round_cards {
+                        Some(next_round_cards) => {
+                            let mut remaining: Vec<Card> = deck.iter().filter(|c| !cards.contains(c)).copied().collect();
+                            let weight = 1. / config.next_round_samples.max(1) as f32;
+                            for _ in 0..config.next_round_samples.max(1) {
+                                let (next_cards, _) = remaining.partial_shuffle(&mut rng, next_round_cards);
+                                let next_board = [board_cards, next_cards].concat();
+                                let strength = expected_hand_strength(evaluator, deck, hole_cards, &next_board, total_board_cards, config.rollout_samples, config.opponent_samples, &mut rng);
+                                histogram[bin(strength.ehs)] += weight;
+                            }
+                        },
+                        None => {
+                            let remaining: Vec<Card> = deck.iter().filter(|c| !cards.contains(c)).copied().collect();
+                            histogram[bin(hand_strength(evaluator, hole_cards, board_cards, &remaining, config.opponent_samples, &mut rng))] = 1.;
+                        },
+                    }
+                }
+            });
+        }
+    });
+
+    info!("Clustering {} hands into {} buckets", num_hands, config.num_buckets);
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+    let buckets = kmeans(&points, config.num_bins, config.num_buckets as usize, config.iterations, config.num_threads, &mut rng);
+
+    let source_hash = config_hash(config);
+    info!("Bucket table source hash {}", source_hash);
+    BucketTable::new(game_info.num_suits(), game_info.num_ranks(), game_info.lowest_rank(), config.round, cards_per_round, config.num_buckets, source_hash, buckets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emd_moves_mass_between_bins() {
+        assert_eq!(emd(&[1., 0., 0.], &[1., 0., 0.]), 0.);
+        assert_eq!(emd(&[1., 0., 0.], &[0., 1., 0.]), 1.);
+        assert_eq!(emd(&[1., 0., 0.], &[0., 0., 1.]), 2.);
+        assert_eq!(emd(&[0., 0., 1.], &[1., 0., 0.]), 2.);
+        assert_eq!(emd(&[0.5, 0., 0.5], &[0., 1., 0.]), 1.);
+    }
+
+    /// Histograms with most of their mass around center and a little noise
+    fn cluster_points(rng: &mut ChaCha8Rng, center: usize, num_bins: usize, n: usize) -> Vec<f32> {
+        (0..n).flat_map(|_| {
+            let mut histogram = vec![0f32; num_bins];
+            let noise = rng.gen_range(0. ..0.2);
+            histogram[center] = 1. - noise;
+            histogram[(center + 1).min(num_bins - 1)] += noise;
+            histogram
+        }).collect()
+    }
+
+    #[test]
+    fn kmeans_separates_clusters_in_strength_order() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let num_bins = 10;
+        let mut points = cluster_points(&mut rng, 8, num_bins, 30);
+        points.extend(cluster_points(&mut rng, 0, num_bins, 30));
+        points.extend(cluster_points(&mut rng, 4, num_bins, 30));
+
+        let buckets = kmeans(&points, num_bins, 3, 20, 2, &mut rng);
+        assert!(buckets[..30].iter().all(|&b| b == 2));
+        assert!(buckets[30..60].iter().all(|&b| b == 0));
+        assert!(buckets[60..].iter().all(|&b| b == 1));
+    }
+
+    #[test]
+    fn source_hash_covers_everything_but_threads() {
+        let config = |seed, num_threads| EmdAbstractionConfig {
+            round: 1,
+            num_buckets: 8,
+            num_bins: 10,
+            next_round_samples: 4,
+            rollout_samples: 4,
+            opponent_samples: 4,
+            iterations: 5,
+            seed,
+            num_threads,
+        };
+        assert_eq!(config_hash(&config(0, 1)), config_hash(&config(0, 8)));
+        assert_ne!(config_hash(&config(0, 1)), config_hash(&config(1, 1)));
+    }
+
+    #[test]
+    fn kmeans_with_one_cluster() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let points = cluster_points(&mut rng, 3, 5, 10);
+        assert_eq!(kmeans(&points, 5, 1, 5, 1, &mut rng), vec![0; 10]);
+    }
+}
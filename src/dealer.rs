@@ -0,0 +1,222 @@
+/*
+* Dealer for the ACPC protocol, runs a match between agents connected over TCP. Agents sit in
+* the order they connected and move one seat to the left every hand, unless the game moves the
+* button instead.
+*/
+
+use super::{
+    acpc::{format_betting, format_hand_cards, format_match_state, parse_action},
+    game::{Action, GameInfo, GameState},
+};
+
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+
+/// Parameters of a match
+#[derive(Debug)]
+pub struct DealerConfig {
+    pub num_hands: u32,
+    /// Seed of the deals, the same seed deals the same cards
+    pub seed: u64,
+}
+
+/// Waits for an agent per player on port, then plays the match writing a line per hand to log.
+/// Returns each agent's total payout in connection order.
+pub fn serve<L: Write>(game_info: &GameInfo, config: &DealerConfig, port: u16, log: &mut L) -> io::Result<Vec<i64>> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    info!("Waiting for {} agents on port {}", game_info.num_players(), port);
+
+    let mut agents = Vec::new();
+    while agents.len() < game_info.num_players() as usize {
+        let (stream, address) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut version = String::new();
+        reader.read_line(&mut version)?;
+        if !version.starts_with("VERSION:2.") {
+            warn!("Rejecting {}, unsupported protocol {:?}", address, version.trim_end());
+            continue;
+        }
+        info!("Agent {} connected from {}", agents.len(), address);
+        agents.push((reader, stream));
+    }
+
+    run_match(game_info, config, &mut agents, log)
+}
+
+/// Plays config.num_hands hands between the agents, one reader and writer per player, and writes
+/// every hand to log in the ACPC log format. Returns each agent's total payout.
+pub fn run_match<R: BufRead, W: Write, L: Write>(game_info: &GameInfo, config: &DealerConfig, agents: &mut [(R, W)], log: &mut L) -> io::Result<Vec<i64>> {
+    let num_players = game_info.num_players() as usize;
+    assert!(agents.len() == num_players, "need an agent for every player");
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+    let mut totals = vec![0i64; num_players];
+
+    for hand in 0..config.num_hands {
+        // seat_agent[s] is the agent sitting in seat s this hand
+        let shift = if game_info.rotate_button { 0 } else { hand as usize % num_players };
+        let seat_agent: Vec<usize> = (0..num_players).map(|s| (s + num_players - shift) % num_players).collect();
+        let deck = game_info.generate_shuffled_deck(&mut rng);
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut state = GameState::new(game_info, hand).with_cards().deal_from(game_info, &deck).map_err(invalid)?;
+        let mut round_actions = vec![Vec::new()];
+
+        loop {
+            let betting = format_betting(game_info, &round_actions);
+            let showdown = state.is_finished() && state.num_folded(game_info) as usize + 1 < num_players;
+            let mut messages = Vec::with_capacity(num_players);
+            for seat in 0..num_players {
+                let visible: Vec<_> = (0..num_players)
+                    .map(|s| if s == seat || (showdown && !state.has_folded(s as u8)) { state.hole_cards(s as u8).to_vec() } else { Vec::new() })
+                    .collect();
+                let message = format_match_state(game_info, seat as u8, hand, &betting, &visible, state.current_round(), state.board_cards());
+                let writer = &mut agents[seat_agent[seat]].1;
+                write!(writer, "{}\r\n", message)?;
+                writer.flush()?;
+                messages.push(message);
+            }
+            if state.is_finished() {
+                break;
+            }
+
+            let seat = state.current_player().unwrap() as usize;
+            let action = read_action(game_info, &state, &messages[seat], &mut agents[seat_agent[seat]].0)?;
+            let round = state.current_round() as usize;
+            state = state.apply_action_no_cards(game_info, action)
+                .and_then(|s| s.deal_from(game_info, &deck))
+                .map_err(invalid)?;
+            round_actions[round].push(action);
+            while round_actions.len() <= state.current_round() as usize {
+                round_actions.push(Vec::new());
+            }
+        }
+
+        let seat_payouts = state.get_dealt_payouts(game_info).map_err(invalid)?;
+        let hole_cards: Vec<_> = (0..num_players).map(|s| state.hole_cards(s as u8).to_vec()).collect();
+        for (seat, &payout) in seat_payouts.iter().enumerate() {
+            totals[seat_agent[seat]] += payout as i64;
+        }
+
+        // STATE:hand:betting:cards:payouts:players with every hand shown
+        let join = |v: Vec<String>| v.join("|");
+        writeln!(log, "STATE:{}:{}:{}:{}:{}",
+            hand,
+            format_betting(game_info, &round_actions),
+            format_hand_cards(game_info, &hole_cards, state.current_round(), state.board_cards()),
+            join(seat_payouts.iter().map(|p| p.to_string()).collect()),
+            join(seat_agent.iter().map(|a| format!("Agent{}", a)).collect()),
+        )?;
+    }
+    log.flush()?;
+
+    Ok(totals)
+}
+
+/// Reads the acting agent's reply to message, actions that aren't legal become calls like the
+/// ACPC dealer does
+fn read_action<R: BufRead>(game_info: &GameInfo, state: &GameState, message: &str, reader: &mut R) -> io::Result<Action> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "agent disconnected"));
+        }
+        let line = line.trim_end();
+        if line.starts_with('#') || line.starts_with(';') || line.is_empty() {
+            continue;
+        }
+
+        let action = line.strip_prefix(message)
+            .and_then(|a| a.strip_prefix(':'))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "reply doesn't match the current state"))?;
+        return Ok(match parse_action(game_info, state.current_round(), action) {
+            Ok(a) if state.is_valid_action(game_info, a) => a,
+            _ => {
+                warn!("Agent sent an illegal action {:?}, calling instead", action);
+                Action::Call
+            },
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io::{Read, BufReader};
+    use std::path::Path;
+    use std::rc::Rc;
+
+    /// Agent that answers the last message it was sent with the next of its scripted actions
+    struct ScriptedAgent {
+        inbox: Rc<RefCell<Vec<u8>>>,
+        actions: VecDeque<&'static str>,
+        reply: Vec<u8>,
+    }
+
+    impl Read for ScriptedAgent {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.reply.is_empty() {
+                let action = match self.actions.pop_front() {
+                    Some(a) => a,
+                    None => return Ok(0),
+                };
+                let inbox = String::from_utf8(self.inbox.borrow().clone()).unwrap();
+                let message = inbox.lines().last().unwrap().trim_end();
+                self.reply = format!("{}:{}\r\n", message, action).into_bytes();
+            }
+            let n = buf.len().min(self.reply.len());
+            buf[..n].copy_from_slice(&self.reply[..n]);
+            self.reply.drain(..n);
+            Ok(n)
+        }
+    }
+
+    struct Inbox(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Inbox {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn agent(actions: &[&'static str]) -> (BufReader<ScriptedAgent>, Inbox) {
+        let inbox = Rc::new(RefCell::new(Vec::new()));
+        let reader = ScriptedAgent { inbox: inbox.clone(), actions: actions.iter().copied().collect(), reply: Vec::new() };
+        (BufReader::new(reader), Inbox(inbox))
+    }
+
+    #[test]
+    fn plays_a_scripted_kuhn_match() {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/kuhn.json"));
+        let config = DealerConfig { num_hands: 2, seed: 5 };
+        // Hand 0 Agent0 bets and Agent1 raises again past the one raise allowed, which gets
+        // turned into a call. Hand 1 has the seats swapped, Agent1 checks and folds to a bet.
+        let mut agents = [agent(&["r", "r"]), agent(&["r", "c", "f"])];
+        let mut log = Vec::new();
+        let totals = run_match(&game_info, &config, &mut agents, &mut log).unwrap();
+
+        let log = String::from_utf8(log).unwrap();
+        assert_eq!(log.lines().collect::<Vec<_>>(), [
+            "STATE:0:rc:3c|4c:-2|2:Agent0|Agent1",
+            "STATE:1:crf:3c|2c:-1|1:Agent1|Agent0",
+        ]);
+        assert_eq!(totals, [-1, 1]);
+        assert_eq!(totals.iter().sum::<i64>(), 0);
+        // Both hands get shown at the showdown, only your own after a fold
+        let inbox = String::from_utf8(agents[1].1.0.borrow().clone()).unwrap();
+        assert!(inbox.contains("MATCHSTATE:1:0:rc:3c|4c\r\n"));
+        assert!(inbox.ends_with("MATCHSTATE:0:1:crf:3c|\r\n"));
+        assert!(agents.iter().all(|(reader, _)| reader.get_ref().actions.is_empty()));
+    }
+}
@@ -0,0 +1,74 @@
+/*
+* Monte Carlo hand strength against a single opponent holding a uniformly random hand, used by
+* the lossy card abstractions
+*/
+
+use poker::{Card, Evaluator};
+
+use rand::prelude::*;
+
+/// Expected hand strength of a hand and its second moment
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HandStrength {
+    /// Probability of winning(ties count half) once every board card is out
+    pub ehs: f64,
+    /// Mean of the squared hand strength over board rollouts, rewards hands that can improve
+    pub ehs2: f64,
+}
+
+/// Samples board_samples completions of board to total_board_cards cards and, for each one,
+/// opponent_samples opponent hands with as many cards as hole_cards. Hole cards plus the full
+/// board have to be at least five cards.
+#[allow(clippy::too_many_arguments)]
+pub fn expected_hand_strength<R: Rng>(evaluator: &Evaluator, deck: &[Card], hole_cards: &[Card], board_cards: &[Card], total_board_cards: usize, board_samples: u32, opponent_samples: u32, rng: &mut R) -> HandStrength {
+    let mut remaining: Vec<Card> = deck.iter()
+        .filter(|c| !hole_cards.contains(c) && !board_cards.contains(c))
+        .copied()
+        .collect();
+    let num_missing = total_board_cards - board_cards.len();
+
+    // Nothing to roll out once the board is complete
+    let board_samples = if num_missing == 0 { 1 } else { board_samples.max(1) };
+
+    let mut strength = HandStrength::default();
+    let mut full_board = board_cards.to_vec();
+    for _ in 0..board_samples {
+        let (rollout, rest) = remaining.partial_shuffle(rng, num_missing);
+        full_board.truncate(board_cards.len());
+        full_board.extend_from_slice(rollout);
+
+        let rest = rest.to_vec();
+        let hs = hand_strength(evaluator, hole_cards, &full_board, &rest, opponent_samples, rng);
+        strength.ehs += hs;
+        strength.ehs2 += hs * hs;
+    }
+
+    strength.ehs /= board_samples as f64;
+    strength.ehs2 /= board_samples as f64;
+    strength
+}
+
+/// Probability hole_cards beat a random opponent hand drawn from remaining on a complete board,
+/// ties count half
+pub fn hand_strength<R: Rng>(evaluator: &Evaluator, hole_cards: &[Card], board_cards: &[Card], remaining: &[Card], opponent_samples: u32, rng: &mut R) -> f64 {
+    let own = evaluator.evaluate([hole_cards, board_cards].concat()).expect("couldn't evaluate hand");
+    let mut remaining = remaining.to_vec();
+    let mut opponent_cards = board_cards.to_vec();
+    let opponent_samples = opponent_samples.max(1);
+
+    let mut wins = 0.;
+    for _ in 0..opponent_samples {
+        let (opponent_hole_cards, _) = remaining.partial_shuffle(rng, hole_cards.len());
+        opponent_cards.truncate(board_cards.len());
+        opponent_cards.extend_from_slice(opponent_hole_cards);
+
+        let opponent = evaluator.evaluate(&opponent_cards).expect("couldn't evaluate hand");
+        wins += match own.cmp(&opponent) {
+            std::cmp::Ordering::Greater => 1.,
+            std::cmp::Ordering::Equal => 0.5,
+            std::cmp::Ordering::Less => 0.,
+        };
+    }
+
+    wins / opponent_samples as f64
+}
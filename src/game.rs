@@ -4,20 +4,23 @@
 
 use log::warn;
 
-use super::action_abstraction::{
-    AbstractRaise, AbstractRaiseType, RaiseRoundConfig
+use super::{
+    acpc,
+    action_abstraction::{AbstractRaise, AbstractRaiseType, RaiseRoundConfig},
+    hand_ranking::{HandRanking, HandValue},
 };
 
-use poker::{Card, Evaluator, Eval, EvalClass, Rank, Suit};
+use poker::{Card, Rank, Suit};
 use itertools::Itertools;
 use variter::VarIter;
 
 use serde::{Deserialize, Serialize};
 
+use rand::prelude::*;
+
 use std::fs;
 use std::fmt;
 use std::option::Option;
-use std::cmp::max;
 use std::path::Path;
 
 pub const MAX_PLAYERS: usize = 22;
@@ -27,7 +30,7 @@ pub const MAX_BOARD_CARDS: usize = 7;
 pub const MAX_HOLE_CARDS: usize = 5;
 
 /// Betting types of a poker game
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub enum BettingType {
     Limit,
     NoLimit,
@@ -53,41 +56,109 @@ impl fmt::Display for Action {
 
 pub type PlayerId = u8;
 
+/// Every card with one of the num_ranks ranks from lowest_rank up and first num_suits suits
+pub fn generate_deck(num_suits: u8, num_ranks: u8, lowest_rank: u8) -> impl Iterator<Item = Card> {
+    Rank::ALL_VARIANTS.iter()
+        .skip(lowest_rank as usize)
+        .take(num_ranks as usize)
+        .cartesian_product(Suit::ALL_VARIANTS.iter().take(num_suits as usize))
+        .map(|(&rank, &suit)| Card::new(rank, suit))
+}
+
 /// Represents the rules and parameters of a poker game
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct GameInfo {
     /// Starting stack for each player
-    starting_stacks: Vec<u32>,
+    pub(crate) starting_stacks: Vec<u32>,
     /// Blinds per player
-    blinds: Vec<u32>,
+    pub(crate) blinds: Vec<u32>,
     /// Size of fixed raises per round for limit games
-    raise_sizes: Vec<u32>,
-    betting_type: BettingType,
-    num_players: PlayerId,
-    num_rounds: u8,
+    pub(crate) raise_sizes: Vec<u32>,
+    pub(crate) betting_type: BettingType,
+    pub(crate) num_players: PlayerId,
+    pub(crate) num_rounds: u8,
     /// Max amount of raises per round
-    max_raises: Vec<u8>,
+    pub(crate) max_raises: Vec<u8>,
     /// First player to act in a round
-    first_player: Vec<PlayerId>,
-    num_suits: u8,
-    num_ranks: u8,
-    num_hole_cards: u8,
+    pub(crate) first_player: Vec<PlayerId>,
+    pub(crate) num_suits: u8,
+    pub(crate) num_ranks: u8,
+    /// Lowest rank in the deck, 0 is a two. Short deck starts at 4 with the sixes.
+    #[serde(default)]
+    pub(crate) lowest_rank: u8,
+    pub(crate) num_hole_cards: u8,
     /// Board cards added each round
-    num_board_cards: Vec<u8>,
+    pub(crate) num_board_cards: Vec<u8>,
+    /// How hands compare at showdown
+    #[serde(default)]
+    pub(crate) hand_ranking: HandRanking,
+    /// Ante every player posts, it goes in the pot without counting towards their bet
+    #[serde(default)]
+    pub(crate) ante: u32,
+    /// Ante the big blind posts on top of ante, for big blind ante games
+    #[serde(default)]
+    pub(crate) big_blind_ante: u32,
+    /// Straddle posted by the first player to act preflop, who then acts last preflop. 0 for no
+    /// straddle.
+    #[serde(default)]
+    pub(crate) straddle: u32,
+    /// Whether the button moves one seat each hand. Stacks, blinds and first players are given
+    /// for the button in seat 0 and move along with it, so every hand plays out like hand 0 with
+    /// the seats turned.
+    #[serde(default)]
+    pub(crate) rotate_button: bool,
 }
 
 impl GameInfo {
+    /// Reads an ACPC game definition if the file ends in .game, our JSON format otherwise
     pub fn load_game_info(path: &Path) -> GameInfo {
-        let game_info: GameInfo = serde_json::from_str(&fs::read_to_string(path).expect("failed to read game info")).expect("failed to deserialize game info");
-        assert!(game_info.starting_stacks.len() as u8 == game_info.num_players);
-        assert!(game_info.blinds.len() as u8 == game_info.num_players);
-        assert!(game_info.raise_sizes.len() as u8 == game_info.num_rounds);
-        assert!(game_info.max_raises.len() as u8 == game_info.num_rounds);
-        assert!(game_info.first_player.len() as u8 == game_info.num_rounds);
-        assert!(game_info.num_board_cards.len() as u8 == game_info.num_rounds);
+        let contents = fs::read_to_string(path).expect("failed to read game info");
+        let game_info: GameInfo = if path.extension().is_some_and(|e| e == "game") {
+            acpc::parse_game_def(&contents).expect("failed to parse ACPC game definition")
+        } else {
+            serde_json::from_str(&contents).expect("failed to deserialize game info")
+        };
+        game_info.validate().expect("invalid game info");
         game_info
     }
 
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.num_players < 2 || self.num_players as usize > MAX_PLAYERS {
+            return Err("number of players is out of range");
+        }
+        if self.num_rounds == 0 || self.num_rounds as usize > MAX_ROUNDS {
+            return Err("number of rounds is out of range");
+        }
+        if self.starting_stacks.len() != self.num_players as usize || self.blinds.len() != self.num_players as usize {
+            return Err("need a stack and a blind for every player");
+        }
+        if self.raise_sizes.len() != self.num_rounds as usize
+            || self.max_raises.len() != self.num_rounds as usize
+            || self.first_player.len() != self.num_rounds as usize
+            || self.num_board_cards.len() != self.num_rounds as usize {
+            return Err("need raise sizes, max raises, first players and board cards for every round");
+        }
+        if self.first_player.iter().any(|&p| p >= self.num_players) {
+            return Err("first player is out of range");
+        }
+        if !(1..=4).contains(&self.num_suits) || !(1..=13).contains(&self.num_ranks) {
+            return Err("deck needs 1 to 4 suits and 1 to 13 ranks");
+        }
+        if self.lowest_rank as usize + self.num_ranks as usize > 13 {
+            return Err("deck has ranks above the ace");
+        }
+        if self.straddle > 0 && (self.num_players < 3 || self.straddle <= self.big_blind()) {
+            return Err("a straddle needs more than two players and has to be bigger than the big blind");
+        }
+        if self.big_blind_ante > 0 && (self.big_blind() == 0 || self.blinds.iter().filter(|&&b| b == self.big_blind()).count() > 1) {
+            return Err("a big blind ante needs a single big blind");
+        }
+        if self.num_hole_cards as usize > MAX_HOLE_CARDS || self.total_board_cards(self.num_rounds - 1) as usize > MAX_BOARD_CARDS {
+            return Err("too many cards");
+        }
+        Ok(())
+    }
+
     pub fn num_suits(&self) -> u8 {
         self.num_suits
     }
@@ -96,6 +167,10 @@ impl GameInfo {
         self.num_ranks
     }
 
+    pub fn lowest_rank(&self) -> u8 {
+        self.lowest_rank
+    }
+
     pub fn num_hole_cards(&self) -> u8 {
         self.num_hole_cards
     }
@@ -104,6 +179,75 @@ impl GameInfo {
         self.num_players
     }
 
+    pub fn num_rounds(&self) -> u8 {
+        self.num_rounds
+    }
+
+    pub fn betting_type(&self) -> &BettingType {
+        &self.betting_type
+    }
+
+    /// Largest blind, used as the unit for mbb/g
+    pub fn big_blind(&self) -> u32 {
+        self.blinds.iter().copied().max().unwrap_or(0)
+    }
+
+    /// Seat of the button in hand hand_id
+    pub fn button(&self, hand_id: u32) -> PlayerId {
+        if self.rotate_button {
+            (hand_id % self.num_players as u32) as PlayerId
+        } else {
+            0
+        }
+    }
+
+    /// Seat player would sit in if the button were in seat 0, the seat the per player settings
+    /// are given for
+    pub fn position(&self, button: PlayerId, player: PlayerId) -> PlayerId {
+        (player + self.num_players - button) % self.num_players
+    }
+
+    fn seat(&self, button: PlayerId, position: PlayerId) -> PlayerId {
+        (position + button) % self.num_players
+    }
+
+    pub fn starting_stack(&self, button: PlayerId, player: PlayerId) -> u32 {
+        self.starting_stacks[self.position(button, player) as usize]
+    }
+
+    /// Blind or straddle player posts
+    pub fn blind(&self, button: PlayerId, player: PlayerId) -> u32 {
+        let position = self.position(button, player);
+        let blind = self.blinds[position as usize];
+        if self.straddle > 0 && position == self.first_player[0] {
+            blind.max(self.straddle)
+        } else {
+            blind
+        }
+    }
+
+    /// Ante player posts, the big blind ante included
+    pub fn ante(&self, button: PlayerId, player: PlayerId) -> u32 {
+        let position = self.position(button, player) as usize;
+        // With tied blinds the big blind is the last of them, validate makes sure there is just
+        // one when it owes an ante
+        let big_blind_position = self.blinds.iter().rposition(|&b| b == self.big_blind());
+        if big_blind_position == Some(position) {
+            self.ante + self.big_blind_ante
+        } else {
+            self.ante
+        }
+    }
+
+    /// First player to act in round, preflop that's the player after the straddle if there is one
+    pub fn first_player(&self, button: PlayerId, round: u8) -> PlayerId {
+        let mut position = self.first_player[round as usize];
+        if round == 0 && self.straddle > 0 {
+            position = (position + 1) % self.num_players;
+        }
+        self.seat(button, position)
+    }
+
     pub fn num_board_cards(&self, round: u8) -> u8 {
         self.num_board_cards[round as usize]
     }
@@ -117,42 +261,31 @@ impl GameInfo {
     }
 
     pub fn generate_deck(&self) -> impl Iterator<Item = Card> {
-        Rank::ALL_VARIANTS.iter()
-            .take(self.num_ranks as usize)
-            .cartesian_product(Suit::ALL_VARIANTS.iter().take(self.num_suits as usize))
-            .map(|(&rank, &suit)| Card::new(rank, suit))
+        generate_deck(self.num_suits, self.num_ranks, self.lowest_rank)
     }
 
-    pub fn generate_shuffled_deck(&self) -> Box<[Card]> {
-        use rand::prelude::*;
-        let mut rng = thread_rng();
+    pub fn generate_shuffled_deck<R: Rng>(&self, rng: &mut R) -> Box<[Card]> {
         let mut cards = self.generate_deck().collect::<Box<_>>();
-        cards.shuffle(&mut rng);
+        cards.shuffle(rng);
         cards
     }
 
-    pub fn deal_hole_cards_and_board_cards(&self) -> ([Vec<Card>; MAX_PLAYERS], Vec<Card>) {
-        let mut hole_cards = [(); MAX_PLAYERS].map(|_| Vec::new());
-        let deck = Vec::from(self.generate_shuffled_deck());
-        let mut c = 0;
-
-        for i in 0..self.num_players {
-            for _ in 0..self.num_hole_cards {
-                hole_cards[i as usize].push(deck[c]);
-                c += 1;
-            }
-        }
-
-        let mut board_cards = Vec::new();
-        for _ in 0..self.total_board_cards((self.num_board_cards.len() - 1) as u8) {
-            board_cards.push(deck[c]);
-            c += 1;
-        }
-
-        (hole_cards, board_cards)
+    /// Every ordered way of dealing the cards of a hand, each one equally likely. Deals are in
+    /// the order GameState::deal_from takes them. Only feasible for small games.
+    pub fn enumerate_deals(&self) -> Vec<Vec<Card>> {
+        let num_cards = self.num_players as usize * self.num_hole_cards as usize + self.total_board_cards(self.num_rounds - 1) as usize;
+        self.generate_deck().permutations(num_cards).collect()
     }
 }
 
+/// Cards dealt so far in a state that tracks them
+#[derive(Clone, Debug, Default)]
+struct DealtCards {
+    hole_cards: [Vec<Card>; MAX_PLAYERS],
+    /// Board cards of every round dealt so far
+    board_cards: Vec<Card>,
+}
+
 /// Represents the state of a poker game
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GameState {
@@ -163,8 +296,12 @@ pub struct GameState {
     min_no_limit_raise_to: u32,
     /// Total amount put into pot by each player
     spent: [u32; MAX_PLAYERS],
-    /// Stack of each player
+    /// Stack of each player less their ante, the most they can bet
     stack_player: [u32; MAX_PLAYERS],
+    /// Ante each player put in the pot, it doesn't count towards their bet
+    ante_paid: [u32; MAX_PLAYERS],
+    /// Seat of the button, see GameInfo::button
+    button: PlayerId,
     /// sum_round_spent[r][p] gives amount in pot for round r of player p
     sum_round_spent: [[u32; MAX_PLAYERS]; MAX_ROUNDS], 
     /// action[r][i] gives the ith action in round r
@@ -179,8 +316,10 @@ pub struct GameState {
     finished: bool,
     /// Which players have folded
     players_folded: [bool; MAX_PLAYERS],
-    // board_cards: Vec<Card>,
-    // hole_cards: [Vec<Card>; MAX_PLAYERS],
+    /// Cards dealt so far, None when the state leaves dealing to the caller. Not serialized,
+    /// states saved with the tree never track cards.
+    #[serde(skip)]
+    cards: Option<Box<DealtCards>>,
 }
 
 impl GameState {
@@ -189,13 +328,21 @@ impl GameState {
         let mut spent = [0; MAX_PLAYERS];
         let mut max_spent: u32 = 0;
         let mut players_folded: [bool; MAX_PLAYERS] = [true; MAX_PLAYERS];
+        let mut stack_player: [u32; MAX_PLAYERS] = [0; MAX_PLAYERS];
+        let mut ante_paid: [u32; MAX_PLAYERS] = [0; MAX_PLAYERS];
+        let button = game_info.button(hand_id);
 
         for i in 0..game_info.num_players {
-            spent[i as usize] = game_info.blinds[i as usize];
-            sum_round_spent[0][i as usize] = game_info.blinds[i as usize];
+            let stack = game_info.starting_stack(button, i);
+            ante_paid[i as usize] = game_info.ante(button, i).min(stack);
+            stack_player[i as usize] = stack - ante_paid[i as usize];
 
-            if game_info.blinds[i as usize] > max_spent {
-                max_spent = game_info.blinds[i as usize];
+            let blind = game_info.blind(button, i).min(stack_player[i as usize]);
+            spent[i as usize] = blind;
+            sum_round_spent[0][i as usize] = blind;
+
+            if blind > max_spent {
+                max_spent = blind;
             }
             players_folded[i as usize] = false;
         }
@@ -206,34 +353,143 @@ impl GameState {
             BettingType::Limit => 0,
         };
 
-        let mut stack_player: [u32; MAX_PLAYERS] = [0; MAX_PLAYERS];
-        for (i, s) in game_info.starting_stacks.iter().enumerate() {
-            stack_player[i] = *s;
-        }
-
         GameState {
             hand_id,
             max_spent,
             min_no_limit_raise_to,
             spent,
             stack_player,
+            ante_paid,
+            button,
             sum_round_spent,
             action: [[None; MAX_NUM_ACTIONS]; MAX_ROUNDS],
             acting_player: [[0; MAX_NUM_ACTIONS]; MAX_ROUNDS],
-            active_player: game_info.first_player[0],
+            active_player: game_info.first_player(button, 0),
             num_actions: [0; MAX_ROUNDS],
             round: 0,
             finished: false,
             players_folded,
-            // board_cards: Vec::new(),
-            // hole_cards: [(); MAX_PLAYERS].map(|_| Vec::new()),
+            cards: None,
+        }
+    }
+
+    /// The same state but tracking cards, each card is dealt with apply_chance before anyone
+    /// who could see it acts
+    pub fn with_cards(mut self) -> GameState {
+        self.cards = Some(Box::default());
+        self
+    }
+
+    /// Returns if the state tracks dealt cards
+    pub fn tracks_cards(&self) -> bool {
+        self.cards.is_some()
+    }
+
+    /// Hole cards dealt to player so far, empty if cards aren't tracked
+    pub fn hole_cards(&self, player: PlayerId) -> &[Card] {
+        self.cards.as_ref().map_or(&[], |c| &c.hole_cards[player as usize])
+    }
+
+    /// Board cards dealt so far, empty if cards aren't tracked
+    pub fn board_cards(&self) -> &[Card] {
+        self.cards.as_ref().map_or(&[], |c| &c.board_cards)
+    }
+
+    /// Every card dealt so far, hole cards in seat order followed by the board
+    pub fn dealt_cards(&self) -> Vec<Card> {
+        match &self.cards {
+            Some(c) => c.hole_cards.iter().flatten().chain(&c.board_cards).copied().collect(),
+            None => Vec::new(),
         }
     }
 
+    /// Returns if a card has to be dealt before play goes on. Hole cards are dealt first, then
+    /// the board of each round as it is reached, nothing is dealt once a single player is left.
+    /// A finished hand can still need the board when players are all in before the last round.
+    pub fn is_chance_node(&self, game_info: &GameInfo) -> bool {
+        let Some(cards) = &self.cards else {
+            return false;
+        };
+        if self.num_folded(game_info) + 1 >= game_info.num_players() {
+            return false;
+        }
+
+        let num_dealt = cards.hole_cards.iter().map(|h| h.len()).sum::<usize>() + cards.board_cards.len();
+        let num_needed = game_info.num_players as usize * game_info.num_hole_cards as usize + game_info.total_board_cards(self.round) as usize;
+        num_dealt < num_needed
+    }
+
+    /// Cards that can be dealt next, each one is equally likely. Empty if no card is due.
+    pub fn chance_outcomes(&self, game_info: &GameInfo) -> Vec<Card> {
+        if !self.is_chance_node(game_info) {
+            return Vec::new();
+        }
+
+        let dealt = self.dealt_cards();
+        game_info.generate_deck().filter(|c| !dealt.contains(c)).collect()
+    }
+
+    /// Probability of each of the chance outcomes, 0 if no card is due
+    pub fn chance_probability(&self, game_info: &GameInfo) -> f64 {
+        if !self.is_chance_node(game_info) {
+            return 0.;
+        }
+
+        let num_cards = game_info.num_suits as usize * game_info.num_ranks as usize;
+        1. / (num_cards - self.dealt_cards().len()) as f64
+    }
+
+    /// Picks the next card to deal, None if no card is due
+    pub fn sample_chance<R: Rng>(&self, game_info: &GameInfo, rng: &mut R) -> Option<Card> {
+        self.chance_outcomes(game_info).choose(rng).copied()
+    }
+
+    /// Returns a new state with card dealt to the first player missing hole cards, or to the
+    /// board once every player has theirs
+    pub fn apply_chance(&self, game_info: &GameInfo, card: Card) -> Result<GameState, &'static str> {
+        if !self.is_chance_node(game_info) {
+            return Err("cannot deal a card when none is due");
+        }
+
+        if !self.chance_outcomes(game_info).contains(&card) {
+            return Err("cannot deal a card that is dealt already or not in the deck");
+        }
+
+        let mut new_state = self.clone();
+        let cards = new_state.cards.as_mut().unwrap();
+        match cards.hole_cards[..game_info.num_players as usize].iter_mut().find(|h| h.len() < game_info.num_hole_cards as usize) {
+            Some(hole_cards) => hole_cards.push(card),
+            None => cards.board_cards.push(card),
+        }
+
+        Ok(new_state)
+    }
+
+    /// Returns a new state with every card that is due dealt from deck. Cards are taken in order
+    /// after the ones dealt so far, so deck has to be where those came from too. Shuffled decks
+    /// and GameInfo::enumerate_deals give the whole hand's cards in this order.
+    pub fn deal_from(&self, game_info: &GameInfo, deck: &[Card]) -> Result<GameState, &'static str> {
+        let mut new_state = self.clone();
+        while new_state.is_chance_node(game_info) {
+            let card = *deck.get(new_state.dealt_cards().len()).ok_or("deck ran out of cards")?;
+            new_state = new_state.apply_chance(game_info, card)?;
+        }
+
+        Ok(new_state)
+    }
+
+    /// The same state tracking cards dealt somewhere else, like the ones a dealer sent. Hole
+    /// cards that weren't shown can be left empty, the cards can then be looked at but not played
+    /// on.
+    pub fn with_dealt_cards(mut self, hole_cards: [Vec<Card>; MAX_PLAYERS], board_cards: Vec<Card>) -> GameState {
+        self.cards = Some(Box::new(DealtCards { hole_cards, board_cards }));
+        self
+    }
+
     pub fn pot_total(&self, game_info: &GameInfo) -> u32 {
         let mut total = 0;
         for i in 0..game_info.num_players {
-            total += self.spent[i as usize]; 
+            total += self.spent[i as usize] + self.ante_paid[i as usize];
         }
         total
     }
@@ -246,6 +502,19 @@ impl GameState {
         self.spent[player as usize]
     }
 
+    pub fn ante_paid(&self, player: PlayerId) -> u32 {
+        self.ante_paid[player as usize]
+    }
+
+    pub fn button(&self) -> PlayerId {
+        self.button
+    }
+
+    /// Largest amount any player has put in the pot
+    pub fn max_spent(&self) -> u32 {
+        self.max_spent
+    }
+
     pub fn current_round(&self) -> u8 {
         self.round
     }
@@ -272,7 +541,7 @@ impl GameState {
     }
 
     /// Returns players who have called
-    pub fn num_called(&self, game_info: &GameInfo) -> u8 {
+    pub fn num_called(&self) -> u8 {
         let mut count = 0;
 
         for i in (0..self.num_actions[self.round as usize]).rev() {
@@ -284,10 +553,9 @@ impl GameState {
                 }
 
                 return count;
-            } else if self.action[self.round as usize][i as usize].unwrap() == Action::Call {
-                if self.spent[player as usize] < self.stack_player[player as usize] {
-                    count += 1;
-                }
+            } else if self.action[self.round as usize][i as usize].unwrap() == Action::Call
+                && self.spent[player as usize] < self.stack_player[player as usize] {
+                count += 1;
             }
         }
 
@@ -346,7 +614,8 @@ impl GameState {
         count
     }
 
-    fn raise_range(&self, game_info: &GameInfo) -> (u32, u32) {
+    /// Smallest and largest amount the current player can raise to, (0, 0) if they can't raise
+    pub fn raise_range(&self, game_info: &GameInfo) -> (u32, u32) {
         if self.finished {
             return (0, 0);
         }
@@ -413,7 +682,8 @@ impl GameState {
                     BettingType::Limit => r == game_info.raise_sizes[self.round as usize],
                     BettingType::NoLimit => {
                         let (min_raise, max_raise) = self.raise_range(game_info);
-                        r >= min_raise && r <= max_raise
+                        // (0, 0) means no raise is possible
+                        max_raise > 0 && r >= min_raise && r <= max_raise
                     }
                 }
             },
@@ -427,17 +697,34 @@ impl GameState {
             RaiseRoundConfig::Before(i) if i > self.num_raises() as u32 => {},
             _ => return None,
         }
+        if !abstract_raise.conditions.allows(game_info, self) {
+            return None;
+        }
 
-        let raise = match abstract_raise.raise_type {
-            AbstractRaiseType::AllIn => Action::Raise(self.stack_player[self.active_player as usize]),
+        let player = self.active_player as usize;
+        let to_call = self.max_spent.saturating_sub(self.spent[player]);
+        let raise_to = match abstract_raise.raise_type {
+            AbstractRaiseType::AllIn => self.stack_player[player],
             AbstractRaiseType::Fixed(i) => {
                 match game_info.betting_type {
-                    BettingType::NoLimit => Action::Raise(self.max_spent + i),
-                    BettingType::Limit => Action::Raise(i)
+                    BettingType::NoLimit => self.max_spent + i,
+                    BettingType::Limit => i
                 }
             },
-            //CHECK: Check below is correct
-            AbstractRaiseType::PotRatio(r) => Action::Raise((self.max_spent as f32 * r) as u32),
+            AbstractRaiseType::PotRatio(r) => self.max_spent + ((self.pot_total(game_info) + to_call) as f32 * r).round() as u32,
+            AbstractRaiseType::StackRatio(r) => self.spent[player] + ((self.stack_player[player] - self.spent[player]) as f32 * r).round() as u32,
+            // min_no_limit_raise_to is always the last raise past max_spent
+            AbstractRaiseType::LastRaiseMultiple(r) => self.max_spent + (self.min_no_limit_raise_to.saturating_sub(self.max_spent) as f32 * r).round() as u32,
+            AbstractRaiseType::MinRaise => self.min_no_limit_raise_to,
+        };
+
+        // Sizes past the stack go all in and sizes under the minimum get raised to it
+        let raise = match game_info.betting_type {
+            BettingType::NoLimit => {
+                let (min_raise, max_raise) = self.raise_range(game_info);
+                Action::Raise(raise_to.clamp(min_raise, max_raise))
+            },
+            BettingType::Limit => Action::Raise(raise_to),
         };
 
         if self.is_valid_action(game_info, raise) {
@@ -447,8 +734,8 @@ impl GameState {
         None
     }
     
-    /// Returns a new state with that action applied, DOES NOT update cards(this may be something
-    /// that gets refactored later).
+    /// Returns a new state with that action applied, DOES NOT deal cards. States that track
+    /// cards need them dealt with apply_chance first.
     pub fn apply_action_no_cards(&self, game_info: &GameInfo, action: Action) -> Result<GameState, &'static str> {
         let mut new_state = self.clone();
 
@@ -456,11 +743,15 @@ impl GameState {
             return Err("cannot apply action to finished state");
         }
 
+        if self.is_chance_node(game_info) {
+            return Err("cannot apply action before the cards due are dealt");
+        }
+
         if self.num_actions[self.round as usize] >= MAX_NUM_ACTIONS as u8 {
             return Err("cannot apply action to state: already at max actions for this round");
         }
 
-        if self.is_valid_action(game_info, action) == false {
+        if !self.is_valid_action(game_info, action) {
             return Err("cannot apply an invalid action");
         }
 
@@ -509,7 +800,7 @@ impl GameState {
 
         if new_state.num_folded(game_info) + 1 >= game_info.num_players() {
             new_state.finished = true;
-        } else if new_state.num_called(game_info) >= new_state.num_active_players(game_info) {
+        } else if new_state.num_called() >= new_state.num_active_players(game_info) {
             if new_state.num_active_players(game_info) > 1 {
                 if new_state.round + 1 < game_info.num_rounds {
                     new_state.round += 1;
@@ -520,7 +811,7 @@ impl GameState {
                         }
                     }
                     new_state.min_no_limit_raise_to += new_state.max_spent;
-                    new_state.active_player = game_info.first_player[new_state.round as usize];
+                    new_state.active_player = game_info.first_player(self.button, new_state.round);
                     while new_state.players_folded[new_state.active_player as usize] || new_state.spent[new_state.active_player as usize] >= new_state.stack_player[new_state.active_player as usize] {
                         new_state.active_player = (new_state.active_player + 1) % game_info.num_players;
                     }
@@ -537,128 +828,450 @@ impl GameState {
         Ok(new_state)
     }
 
-    pub fn get_payout(&self, game_info: &GameInfo, evaluator: &Evaluator, board_cards: &[Card], hole_cards: &[Vec<Card>; MAX_PLAYERS], player: PlayerId) -> i32 {
+    /// Net chips player wins or loses with the cards the state dealt, folded players can be
+    /// asked before the hand is over
+    pub fn get_dealt_payout(&self, game_info: &GameInfo, player: PlayerId) -> Result<i32, &'static str> {
         if self.has_folded(player) {
-            return  -(self.spent[player as usize] as i32);
+            return Ok(-((self.spent[player as usize] + self.ante_paid[player as usize]) as i32));
         }
 
-        if !self.is_finished() {
-            panic!("cannot calculate payout when the hand is not over or the player has not folded!");
+        Ok(self.get_dealt_payouts(game_info)?[player as usize])
+    }
+
+    /// get_payouts with the cards the state dealt
+    pub fn get_dealt_payouts(&self, game_info: &GameInfo) -> Result<Vec<i32>, &'static str> {
+        let Some(cards) = &self.cards else {
+            return Err("cannot calculate payouts from a state that doesn't track cards");
+        };
+        if !self.is_finished() || self.is_chance_node(game_info) {
+            return Err("cannot calculate payouts before the hand is over");
         }
 
-        if self.num_folded(game_info) + 1 == game_info.num_players() {
-            let mut value = 0;
+        Ok(self.get_payouts(game_info, &cards.board_cards, &cards.hole_cards))
+    }
 
-            for i in 0..game_info.num_players() {
-                if i == player {
-                    continue;
-                }
+    /// Net chips every player wins or loses in a finished hand, they always sum to zero. The pot
+    /// is split into a main pot and side pots at each amount, antes included, a player still in
+    /// the hand put in, every pot goes to the best hands among the players who put in that much.
+    /// Chips that don't split evenly go one at a time to the winners closest after the first
+    /// player of the last round.
+    pub fn get_payouts(&self, game_info: &GameInfo, board_cards: &[Card], hole_cards: &[Vec<Card>; MAX_PLAYERS]) -> Vec<i32> {
+        assert!(self.is_finished(), "cannot calculate payouts when the hand is not over");
+        let num_players = game_info.num_players() as usize;
+        let put_in: Vec<u32> = (0..num_players).map(|p| self.spent[p] + self.ante_paid[p]).collect();
+        let mut payouts: Vec<i32> = put_in.iter().map(|&s| -(s as i32)).collect();
+
+        let contenders: Vec<usize> = (0..num_players).filter(|&p| !self.has_folded(p as PlayerId)).collect();
+        if let [winner] = contenders[..] {
+            payouts[winner] += self.pot_total(game_info) as i32;
+            return payouts;
+        }
+
+        let ranker = game_info.hand_ranking.ranker();
+        let ranks: Vec<Option<HandValue>> = (0..num_players)
+            .map(|p| (!self.has_folded(p as PlayerId)).then(|| ranker.rank(&[&hole_cards[p][..], board_cards].concat(), game_info.lowest_rank)))
+            .collect();
+        // Seats in the order odd chips are handed out
+        let first = game_info.first_player(self.button, game_info.num_rounds - 1) as usize;
+        let order: Vec<usize> = (0..num_players).map(|i| (first + i) % num_players).collect();
+
+        let mut levels: Vec<u32> = contenders.iter().map(|&p| put_in[p]).collect();
+        levels.sort_unstable();
+        levels.dedup();
+        let top = *levels.last().unwrap();
+
+        let mut previous = 0;
+        for level in levels {
+            // The last pot also takes anything folded players put in past every contender
+            let cap = if level == top { u32::MAX } else { level };
+            let pot: u32 = put_in.iter().map(|&s| s.min(cap) - s.min(previous)).sum();
+            previous = level;
+            if pot == 0 {
+                continue;
+            }
+
+            let eligible = || contenders.iter().copied().filter(|&p| put_in[p] >= level);
+            let best = eligible().filter_map(|p| ranks[p]).max().unwrap();
+            let winners: Vec<usize> = order.iter().copied().filter(|&p| put_in[p] >= level && ranks[p] == Some(best)).collect();
 
-                value += self.spent[i as usize];
+            let share = pot / winners.len() as u32;
+            let odd_chips = (pot % winners.len() as u32) as usize;
+            for (i, &w) in winners.iter().enumerate() {
+                payouts[w] += (share + (i < odd_chips) as u32) as i32;
             }
+        }
+
+        payouts
+    }
+}
 
-            //CHECK: maybe don't wanna do shit like this?
-            return i32::try_from(value).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{acpc::parse_cards, action_abstraction::RaiseConditions};
+
+    fn holdem_nl() -> GameInfo {
+        GameInfo::load_game_info(Path::new("game_configs/holdem_nl.json"))
+    }
+
+    fn raise(raise_type: AbstractRaiseType) -> AbstractRaise {
+        AbstractRaise {
+            raise_type,
+            round_config: (0..4).map(|_| RaiseRoundConfig::Always).collect(),
+            conditions: RaiseConditions::default(),
         }
+    }
 
-        let mut rank = vec![None; game_info.num_players().into()];
-        let mut spent = vec![0; game_info.num_players().into()];
-        let mut players_left: i32 = 0;
-        let mut player_idx: i32 = -1;
+    fn raise_to(game_info: &GameInfo, state: &GameState, raise_type: AbstractRaiseType) -> Option<Action> {
+        state.abstract_raise_to_real(game_info, &raise(raise_type))
+    }
 
-        for i in 0..game_info.num_players() {
-            if self.spent[i as usize] == 0 {
-                continue;
-            }
+    /// The small blind raises to 300, leaving the big blind facing a 200 chip bet into a 400 pot
+    fn facing_raise(game_info: &GameInfo) -> GameState {
+        GameState::new(game_info, 0).apply_action_no_cards(game_info, Action::Raise(300)).unwrap()
+    }
 
-            if self.has_folded(i) {
-                rank[players_left as usize] = None;
-            } else {
-                if i == player {
-                    player_idx = players_left;
-                }
+    #[test]
+    fn pot_ratio_raises_by_the_pot_after_calling() {
+        let game_info = holdem_nl();
+        // Calling makes the pot 600, so a pot raise is to 300 + 600
+        let state = facing_raise(&game_info);
+        assert_eq!(raise_to(&game_info, &state, AbstractRaiseType::PotRatio(1.)), Some(Action::Raise(900)));
+        assert_eq!(raise_to(&game_info, &state, AbstractRaiseType::PotRatio(0.5)), Some(Action::Raise(600)));
+    }
 
-                let cards = [&hole_cards[i as usize][..], board_cards].concat();
+    #[test]
+    fn stack_ratio_puts_in_part_of_the_remaining_stack() {
+        let game_info = holdem_nl();
+        // The small blind has 19950 behind, half of it on top of the 50 already in
+        let state = GameState::new(&game_info, 0);
+        assert_eq!(raise_to(&game_info, &state, AbstractRaiseType::StackRatio(0.5)), Some(Action::Raise(10025)));
+    }
 
-                // CHECK: Special cases for Kuhn poker and Leduc poker, I should check that EvalClass is enough to
-                // compare hands
-                if cards.len() == 1 {
-                    rank[players_left as usize] = Some(EvalClass::HighCard { high_rank: cards[0].rank() });
-                } else if cards.len() == 2 {
-                    if cards[0].rank() == cards[1].rank() {
-                        rank[players_left as usize] = Some(EvalClass::Pair { pair: cards[0].rank() });
-                    } else {
-                        rank[players_left as usize] = Some(EvalClass::HighCard { high_rank: max(cards[0].rank(), cards[1].rank()) })
-                    }
-                } else {
-                    rank[players_left as usize] = Some(evaluator.evaluate(cards).expect("couldn't evaluate hand").class());
-                }
-            }
+    #[test]
+    fn last_raise_multiple_scales_the_last_raise() {
+        let game_info = holdem_nl();
+        // The last raise was 200, so twice that on top of 300
+        let state = facing_raise(&game_info);
+        assert_eq!(raise_to(&game_info, &state, AbstractRaiseType::LastRaiseMultiple(2.)), Some(Action::Raise(700)));
+    }
 
-            spent[players_left as usize] = self.spent[i as usize];
-            players_left += 1;
+    #[test]
+    fn last_raise_multiple_falls_back_to_the_big_blind() {
+        let game_info = holdem_nl();
+        let state = GameState::new(&game_info, 0)
+            .apply_action_no_cards(&game_info, Action::Call).unwrap()
+            .apply_action_no_cards(&game_info, Action::Call).unwrap();
+        assert_eq!(state.round, 1);
+        // Nobody has bet on the flop so twice the big blind is a bet of 200
+        assert_eq!(raise_to(&game_info, &state, AbstractRaiseType::LastRaiseMultiple(2.)), Some(Action::Raise(300)));
+    }
+
+    #[test]
+    fn min_raise_matches_the_last_raise() {
+        let game_info = holdem_nl();
+        assert_eq!(raise_to(&game_info, &GameState::new(&game_info, 0), AbstractRaiseType::MinRaise), Some(Action::Raise(200)));
+        assert_eq!(raise_to(&game_info, &facing_raise(&game_info), AbstractRaiseType::MinRaise), Some(Action::Raise(500)));
+    }
+
+    #[test]
+    fn sizes_are_clamped_to_the_legal_range() {
+        let mut game_info = holdem_nl();
+        let state = GameState::new(&game_info, 0);
+        // A tenth of the pot is under the min raise to 200
+        assert_eq!(raise_to(&game_info, &state, AbstractRaiseType::PotRatio(0.1)), Some(Action::Raise(200)));
+
+        game_info.starting_stacks = vec![1000, 1000];
+        let state = GameState::new(&game_info, 0);
+        // A raise to 100 + 10 * 200 is past the 1000 chip stack
+        assert_eq!(raise_to(&game_info, &state, AbstractRaiseType::PotRatio(10.)), Some(Action::Raise(1000)));
+    }
+
+    /// Three handed no limit hold'em, seat 0 posts the small blind and seat 2 acts first preflop
+    fn three_handed(starting_stacks: [u32; 3], blinds: [u32; 3]) -> GameInfo {
+        let mut game_info = holdem_nl();
+        game_info.num_players = 3;
+        game_info.starting_stacks = starting_stacks.to_vec();
+        game_info.blinds = blinds.to_vec();
+        game_info.first_player = vec![2, 0, 0, 0];
+        game_info.validate().unwrap();
+        game_info
+    }
+
+    fn play(game_info: &GameInfo, actions: &[Action]) -> GameState {
+        let state = actions.iter().fold(GameState::new(game_info, 0), |state, &action| {
+            state.apply_action_no_cards(game_info, action).unwrap()
+        });
+        assert!(state.is_finished());
+        state
+    }
+
+    fn hands(hands: &[&str]) -> [Vec<Card>; MAX_PLAYERS] {
+        let mut hole_cards = [(); MAX_PLAYERS].map(|_| Vec::new());
+        for (player, hand) in hands.iter().enumerate() {
+            hole_cards[player] = parse_cards(hand).unwrap();
         }
+        hole_cards
+    }
+
+    /// Rainbow board without straights, the best pair in the hole wins
+    fn board() -> Vec<Card> {
+        parse_cards("2c7d9hJs3d").unwrap()
+    }
 
-        assert!(players_left > 1);
-        assert!(player_idx > -1);
+    /// Seat 2 shoves for 3000 and gets called by seat 0 for 1000 and seat 1 for 2000
+    fn three_way_all_in(game_info: &GameInfo) -> GameState {
+        play(game_info, &[Action::Raise(3000), Action::Call, Action::Call])
+    }
 
-        let mut player_idx = player_idx as usize;
+    #[test]
+    fn all_in_pots_go_to_the_best_eligible_hand() {
+        let game_info = three_handed([1000, 2000, 3000], [50, 100, 0]);
+        let state = three_way_all_in(&game_info);
+        // Seat 0 takes the 3000 main pot, seat 1 the 2000 side pot and seat 2 gets back the
+        // 1000 nobody called
+        let payouts = state.get_payouts(&game_info, &board(), &hands(&["AsAh", "KsKh", "QsQh"]));
+        assert_eq!(payouts, [2000, 0, -2000]);
+        // With the hands reversed the big stack wins every pot
+        let payouts = state.get_payouts(&game_info, &board(), &hands(&["QsQh", "KsKh", "AsAh"]));
+        assert_eq!(payouts, [-1000, -2000, 3000]);
+    }
 
-        let mut value: i32 = 0;
+    #[test]
+    fn split_main_pot_with_a_lone_side_pot_winner() {
+        let game_info = three_handed([1000, 2000, 3000], [50, 100, 0]);
+        let state = three_way_all_in(&game_info);
+        // Seats 0 and 2 split the main pot, seat 2 takes the side pot alone
+        let payouts = state.get_payouts(&game_info, &board(), &hands(&["AsAh", "KsKh", "AdAc"]));
+        assert_eq!(payouts, [500, -2000, 1500]);
+    }
 
-        loop {
-            let mut size = u32::MAX;
-            //CHECK: if this win_rank is correct, doing this for kuhn/leduc poker to work
-            let mut win_rank = EvalClass::HighCard { high_rank: Rank::Two };
-            let mut num_winners: i32 = 0;
+    #[test]
+    fn odd_chips_go_to_the_first_winner_after_the_first_player() {
+        let game_info = three_handed([1000, 1000, 1000], [25, 50, 0]);
+        // Seat 2 limps, the small blind folds and the hand gets checked down into a 125 chip pot
+        let mut actions = vec![Action::Call, Action::Fold, Action::Call];
+        actions.extend([Action::Call; 6]);
+        let state = play(&game_info, &actions);
+        // Seat 1 comes first after seat 0 postflop, so it gets the odd chip
+        let payouts = state.get_payouts(&game_info, &board(), &hands(&["", "KsKh", "KdKc"]));
+        assert_eq!(payouts, [-25, 13, 12]);
+    }
 
-            for i in 0..players_left {
-                assert!(spent[i as usize] > 0);
+    #[test]
+    fn chips_folded_past_a_short_stack_go_to_the_side_pot() {
+        let game_info = three_handed([5000, 5000, 300], [50, 100, 0]);
+        // Seat 2 shoves 300, seats 0 and 1 play on to 1000 each and seat 1 folds to a flop bet
+        let state = play(&game_info, &[
+            Action::Raise(300), Action::Raise(1000), Action::Call,
+            Action::Raise(3000), Action::Fold,
+        ]);
+        // Seat 2 wins the 900 main pot, everything seat 1 put in past 300 goes to seat 0
+        let payouts = state.get_payouts(&game_info, &board(), &hands(&["KsKh", "", "AsAh"]));
+        assert_eq!(payouts, [400, -1000, 600]);
+    }
 
-                if spent[i as usize] < size {
-                    size = spent[i as usize];
-                }
+    #[test]
+    fn antes_count_towards_all_in_pot_levels() {
+        let mut game_info = three_handed([1000, 2000, 3000], [50, 100, 0]);
+        game_info.ante = 10;
+        // Each seat has 10 less to bet, so with the antes everyone is in for their whole stack
+        let state = play(&game_info, &[Action::Raise(2990), Action::Call, Action::Call]);
+        let payouts = state.get_payouts(&game_info, &board(), &hands(&["AsAh", "KsKh", "QsQh"]));
+        assert_eq!(payouts, [2000, 0, -2000]);
+        let payouts = state.get_payouts(&game_info, &board(), &hands(&["QsQh", "KsKh", "AsAh"]));
+        assert_eq!(payouts, [-1000, -2000, 3000]);
+    }
 
-                if let Some(r) = rank[i as usize] {
-                    if r > win_rank {
-                        win_rank = r;
-                        num_winners = 1;
-                    } else if r == win_rank {
-                        num_winners += 1;
-                    }
-                }
+    /// Calls f on every finished state the fold, call, min raise and all in actions reach
+    fn for_each_finished_state<F: FnMut(&GameState)>(game_info: &GameInfo, state: &GameState, f: &mut F) {
+        if state.is_finished() {
+            f(state);
+            return;
+        }
+        let (min_raise, max_raise) = state.raise_range(game_info);
+        let actions = [Action::Fold, Action::Call, Action::Raise(min_raise), Action::Raise(max_raise)];
+        for action in actions.into_iter().dedup() {
+            if state.is_valid_action(game_info, action) {
+                for_each_finished_state(game_info, &state.apply_action_no_cards(game_info, action).unwrap(), f);
             }
+        }
+    }
 
-            if rank[player_idx as usize].unwrap()== win_rank {
-                value += (size as i32) * (players_left - num_winners) / num_winners;
-            } else {
-                value -= size as i32;
+    #[test]
+    fn payouts_are_zero_sum() {
+        let game_info = three_handed([300, 500, 700], [50, 100, 0]);
+        // Tied hands and every order of hand strengths between the seats
+        let deals = [
+            hands(&["AsAh", "KsKh", "QsQh"]),
+            hands(&["QsQh", "AsAh", "KsKh"]),
+            hands(&["KsKh", "QsQh", "AsAh"]),
+            hands(&["AsAh", "AdAc", "QsQh"]),
+            hands(&["AsAh", "AdAc", "KsKh"]),
+        ];
+        let mut num_states = 0;
+        for_each_finished_state(&game_info, &GameState::new(&game_info, 0), &mut |state| {
+            num_states += 1;
+            for hole_cards in &deals {
+                let payouts = state.get_payouts(&game_info, &board(), hole_cards);
+                assert_eq!(payouts.iter().sum::<i32>(), 0, "{:?}", state);
             }
+        });
+        assert!(num_states > 1000, "only {} states", num_states);
+    }
 
-            let mut new_players_left = 0;
-            for i in 0..players_left as usize {
-                spent[i] -= size;
-                if spent[i] == 0 {
-                    if i == player_idx as usize {
-                        return value;
-                    }
+    #[test]
+    fn short_deck_deals_sixes_up_and_ranks_flushes_over_full_houses() {
+        let mut game_info = GameInfo::load_game_info(Path::new("game_configs/holdem_nl_short_deck.json"));
+        let deck: Vec<Card> = game_info.generate_deck().collect();
+        assert_eq!(deck.len(), 36);
+        assert_eq!(deck.iter().unique().count(), 36);
+        assert!(deck.iter().all(|c| c.rank() >= Rank::Six));
+
+        // The small blind limps and the hand is checked down
+        let state = play(&game_info, &[Action::Call; 8]);
+        let flush_over_full_house = state.get_payouts(&game_info, &parse_cards("7h8h9hAsAd").unwrap(), &hands(&["KhQh", "Ac9s"]));
+        assert_eq!(flush_over_full_house, [100, -100]);
+        let ace_to_nine_straight = state.get_payouts(&game_info, &parse_cards("6c7d8sKhQh").unwrap(), &hands(&["As9d", "KsKd"]));
+        assert_eq!(ace_to_nine_straight, [100, -100]);
+
+        game_info.lowest_rank = 5;
+        assert!(game_info.validate().is_err());
+    }
 
-                    continue;
-                }
+    fn holdem_nl_6max() -> GameInfo {
+        GameInfo::load_game_info(Path::new("game_configs/holdem_nl_6max.json"))
+    }
 
-                if i == player_idx {
-                    player_idx = i;
-                }
+    #[test]
+    fn blinds_and_antes_follow_the_button() {
+        let game_info = holdem_nl_6max();
+        // The button is in seat 1 on hand 7, so seat 2 posts the small blind and seat 3 the big
+        // blind with its ante
+        let button = game_info.button(7);
+        assert_eq!(button, 1);
+        let blinds: Vec<u32> = (0..6).map(|p| game_info.blind(button, p)).collect();
+        assert_eq!(blinds, [0, 0, 50, 100, 0, 0]);
+        let antes: Vec<u32> = (0..6).map(|p| game_info.ante(button, p)).collect();
+        assert_eq!(antes, [0, 0, 0, 100, 0, 0]);
+        assert_eq!(game_info.first_player(button, 0), 4);
+        assert_eq!(game_info.first_player(button, 1), 2);
+    }
 
-                if i != new_players_left {
-                    spent[new_players_left] = spent[i];
-                    spent[new_players_left] = spent[i];
-                }
+    #[test]
+    fn big_blind_ante_needs_a_single_big_blind() {
+        let mut game_info = holdem_nl_6max();
+        game_info.blinds = vec![0, 100, 100, 0, 0, 0];
+        assert!(game_info.validate().is_err());
+
+        // Kuhn and Leduc post equal blinds
+        let mut game_info = GameInfo::load_game_info(Path::new("game_configs/kuhn.json"));
+        game_info.big_blind_ante = 1;
+        assert!(game_info.validate().is_err());
+
+        // Ante only games have no big blind to put it on, a plain ante is fine
+        let mut game_info = holdem_nl_6max();
+        game_info.blinds = vec![0; 6];
+        assert!(game_info.validate().is_err());
+        game_info.big_blind_ante = 0;
+        game_info.ante = 25;
+        game_info.validate().unwrap();
+        let button = game_info.button(7);
+        assert!((0..6).all(|p| game_info.ante(button, p) == 25));
+    }
 
-                new_players_left += 1;
-            }
-            players_left = new_players_left as i32;
+    #[test]
+    fn straddle_posts_under_the_gun_and_acts_last_preflop() {
+        let mut game_info = holdem_nl_6max();
+        game_info.straddle = 200;
+        let button = game_info.button(7);
+        assert_eq!(game_info.blind(button, 4), 200);
+        assert_eq!(game_info.blind(button, 3), 100);
+        // Preflop action starts after the straddle, later rounds don't change
+        assert_eq!(game_info.first_player(button, 0), 5);
+        assert_eq!(game_info.first_player(button, 1), 2);
+
+        let state = GameState::new(&game_info, 7);
+        assert_eq!(state.current_player(), Ok(5));
+        assert_eq!(state.max_spent(), 200);
+    }
+
+    #[test]
+    fn apply_chance_deals_hole_cards_seat_by_seat_then_the_board() {
+        let game_info = holdem_nl();
+        let cards = parse_cards("AsKs2c3d4h5s6c").unwrap();
+        let mut state = GameState::new(&game_info, 0).with_cards();
+        for &card in &cards[..4] {
+            assert_eq!(state.chance_probability(&game_info), 1. / (52 - state.dealt_cards().len()) as f64);
+            state = state.apply_chance(&game_info, card).unwrap();
         }
+        assert_eq!(state.hole_cards(0), &cards[..2]);
+        assert_eq!(state.hole_cards(1), &cards[2..4]);
+        assert!(state.board_cards().is_empty());
+
+        // Nothing more is due until the preflop betting is over
+        assert!(!state.is_chance_node(&game_info));
+        assert_eq!(state.chance_probability(&game_info), 0.);
+        assert!(state.apply_chance(&game_info, cards[4]).is_err());
+
+        let state = state.apply_action_no_cards(&game_info, Action::Call).unwrap()
+            .apply_action_no_cards(&game_info, Action::Call).unwrap()
+            .deal_from(&game_info, &cards).unwrap();
+        assert_eq!(state.board_cards(), &cards[4..]);
+        assert!(!state.is_chance_node(&game_info));
+    }
+
+    #[test]
+    fn all_in_runs_out_the_board() {
+        let game_info = holdem_nl();
+        let state = GameState::new(&game_info, 0).with_cards()
+            .deal_from(&game_info, &parse_cards("AsKs2c3d").unwrap()).unwrap()
+            .apply_action_no_cards(&game_info, Action::Raise(20000)).unwrap()
+            .apply_action_no_cards(&game_info, Action::Call).unwrap();
+        assert!(state.is_finished());
+
+        // The whole board is due before the hand can be paid out
+        let mut state = state;
+        for card in parse_cards("4h5s8cJd9h").unwrap() {
+            assert!(state.is_chance_node(&game_info));
+            assert!(state.get_dealt_payouts(&game_info).is_err());
+            state = state.apply_chance(&game_info, card).unwrap();
+        }
+        assert!(!state.is_chance_node(&game_info));
+        assert_eq!(state.get_dealt_payouts(&game_info), Ok(vec![20000, -20000]));
+    }
+
+    #[test]
+    fn apply_chance_rejects_a_dealt_card() {
+        let game_info = holdem_nl();
+        let ace = parse_cards("As").unwrap()[0];
+        let state = GameState::new(&game_info, 0).with_cards().apply_chance(&game_info, ace).unwrap();
+        assert!(!state.chance_outcomes(&game_info).contains(&ace));
+        assert!(state.apply_chance(&game_info, ace).is_err());
+        assert!(state.deal_from(&game_info, &parse_cards("AsAsKsQs").unwrap()).is_err());
     }
-}
 
+    #[test]
+    fn sample_chance_is_reproducible_from_the_seed() {
+        let game_info = holdem_nl();
+        // Checks the hand down, dealing every card that comes due from rng
+        let deal = |seed: u64| {
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed);
+            let mut state = GameState::new(&game_info, 0).with_cards();
+            loop {
+                state = match state.sample_chance(&game_info, &mut rng) {
+                    Some(card) => state.apply_chance(&game_info, card).unwrap(),
+                    None if !state.is_finished() => state.apply_action_no_cards(&game_info, Action::Call).unwrap(),
+                    None => break,
+                };
+            }
+            state.dealt_cards()
+        };
+        let cards = deal(3);
+        assert_eq!(cards.len(), 9);
+        assert_eq!(cards.iter().unique().count(), 9);
+        assert_eq!(deal(3), cards);
+        assert_ne!(deal(4), cards);
+    }
+}
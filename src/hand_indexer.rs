@@ -0,0 +1,528 @@
+/*
+* Suit isomorphic hand indexing from "A Fast and Optimal Hand Isomorphism Algorithm" by Kevin
+* Waugh(http://www.kevinwaugh.com/pdf/isomorphism13.pdf), adapted from his C implementation to
+* any number of suits and ranks. Hands that only differ by a permutation of suits get the same
+* index and every round's indices are dense, 0..round_size(round).
+*/
+
+use poker::{Card, Rank, Suit};
+use variter::VarIter;
+
+use std::cmp::min;
+
+const MAX_SUITS: usize = 4;
+const MAX_RANKS: usize = 13;
+/// Bits used for the number of cards a suit gets in one round
+const ROUND_SHIFT: usize = 4;
+const ROUND_MASK: u32 = 0xf;
+
+/// Number of cards each suit gets in each round, sorted so isomorphic hands share one
+#[derive(Debug)]
+struct Configuration {
+    /// counts[s] packs the cards of suit s per round, earlier rounds in the higher bits
+    counts: [u32; MAX_SUITS],
+    /// Number of rank combinations suit s can have in this configuration
+    suit_size: [u64; MAX_SUITS],
+    /// Bit s is set when suit s has the same counts as suit s - 1
+    equal: u32,
+    /// First index of the configuration in its round
+    offset: u64,
+}
+
+/// Incremental indexing state, hands are indexed one round at a time
+struct IndexState {
+    suit_index: [u64; MAX_SUITS],
+    suit_multiplier: [u64; MAX_SUITS],
+    used_ranks: [u32; MAX_SUITS],
+    permutation_index: usize,
+    permutation_multiplier: usize,
+}
+
+pub struct HandIndexer {
+    num_suits: usize,
+    num_ranks: usize,
+    /// Rank of the lowest card, cards are indexed by their rank above it
+    lowest_rank: usize,
+    cards_per_round: Vec<u8>,
+    /// Position of each round's first card in a hand
+    round_start: Vec<usize>,
+    /// configurations[r] sorted by counts
+    configurations: Vec<Vec<Configuration>>,
+    /// Configuration and suit permutation of each unsorted count vector, by permutation index
+    permutation_to_configuration: Vec<Vec<usize>>,
+    permutation_to_pi: Vec<Vec<[usize; MAX_SUITS]>>,
+    round_size: Vec<u64>,
+}
+
+impl HandIndexer {
+    /// Indexer for hands dealt over rounds where cards_per_round[r] cards come in round r, the
+    /// first round usually being the hole cards, from a deck of num_ranks ranks starting at
+    /// lowest_rank
+    pub fn new(num_suits: u8, num_ranks: u8, lowest_rank: u8, cards_per_round: &[u8]) -> HandIndexer {
+        let num_suits = num_suits as usize;
+        let num_ranks = num_ranks as usize;
+        let lowest_rank = lowest_rank as usize;
+        assert!((1..=MAX_SUITS).contains(&num_suits), "hand indexer supports 1 to 4 suits");
+        assert!((1..=MAX_RANKS).contains(&num_ranks) && lowest_rank + num_ranks <= MAX_RANKS, "hand indexer supports 1 to 13 ranks");
+        assert!(!cards_per_round.is_empty() && cards_per_round.len() * ROUND_SHIFT <= 32, "hand indexer supports 1 to 8 rounds");
+        assert!(cards_per_round.iter().all(|&c| (c as u32) <= ROUND_MASK), "too many cards in a round");
+        assert!(cards_per_round.iter().map(|&c| c as usize).sum::<usize>() <= num_suits * num_ranks, "more cards than in the deck");
+
+        let num_rounds = cards_per_round.len();
+        let round_start = cards_per_round.iter()
+            .scan(0, |start, &c| {
+                let s = *start;
+                *start += c as usize;
+                Some(s)
+            })
+            .collect();
+
+        let mut indexer = HandIndexer {
+            num_suits,
+            num_ranks,
+            lowest_rank,
+            cards_per_round: cards_per_round.to_vec(),
+            round_start,
+            configurations: (0..num_rounds).map(|_| Vec::new()).collect(),
+            permutation_to_configuration: Vec::new(),
+            permutation_to_pi: Vec::new(),
+            round_size: vec![0; num_rounds],
+        };
+
+        let mut counts: Vec<Vec<[u32; MAX_SUITS]>> = vec![Vec::new(); num_rounds];
+        indexer.enumerate_counts(true, &mut |round, c| counts[round].push(*c));
+        for (round, mut round_counts) in counts.into_iter().enumerate() {
+            round_counts.sort();
+            let mut offset = 0;
+            for c in round_counts {
+                let configuration = indexer.configuration(round, c, offset);
+                offset += indexer.configuration_size(&configuration);
+                indexer.configurations[round].push(configuration);
+            }
+            indexer.round_size[round] = offset;
+        }
+
+        indexer.permutation_to_configuration = (0..num_rounds).map(|r| vec![0; indexer.num_permutations(r)]).collect();
+        indexer.permutation_to_pi = (0..num_rounds).map(|r| vec![[0; MAX_SUITS]; indexer.num_permutations(r)]).collect();
+        let mut permutations = Vec::new();
+        indexer.enumerate_counts(false, &mut |round, c| permutations.push((round, *c)));
+        for (round, c) in permutations {
+            indexer.tabulate_permutation(round, &c);
+        }
+
+        indexer
+    }
+
+    pub fn num_rounds(&self) -> usize {
+        self.cards_per_round.len()
+    }
+
+    /// Number of distinct indices of round
+    pub fn round_size(&self, round: usize) -> u64 {
+        self.round_size[round]
+    }
+
+    /// Index of cards, which have to hold every card up to the end of some round in the order they
+    /// were dealt. The index is that of the last round covered by cards.
+    pub fn index(&self, cards: &[Card]) -> u64 {
+        let mut state = IndexState {
+            suit_index: [0; MAX_SUITS],
+            suit_multiplier: [1; MAX_SUITS],
+            used_ranks: [0; MAX_SUITS],
+            permutation_index: 0,
+            permutation_multiplier: 1,
+        };
+
+        let mut index = 0;
+        let mut round = 0;
+        while round < self.num_rounds() && self.round_start[round] + (self.cards_per_round[round] as usize) <= cards.len() {
+            let start = self.round_start[round];
+            index = self.index_next_round(round, &cards[start..start + self.cards_per_round[round] as usize], &mut state);
+            round += 1;
+        }
+        assert!(round > 0 && self.round_start.get(round).is_none_or(|&start| start == cards.len()), "cards don't end on a round");
+
+        index
+    }
+
+    /// Canonical hand with the given index in round, None if the index is out of range
+    pub fn unindex(&self, round: usize, index: u64) -> Option<Vec<Card>> {
+        if round >= self.num_rounds() || index >= self.round_size[round] {
+            return None;
+        }
+
+        let configurations = &self.configurations[round];
+        let configuration = &configurations[configurations.partition_point(|c| c.offset <= index) - 1];
+        let mut index = index - configuration.offset;
+
+        let mut suit_index = [0; MAX_SUITS];
+        let mut i = 0;
+        while i < self.num_suits {
+            let j = self.group_end(configuration, i);
+            let suit_size = configuration.suit_size[i];
+            let group_size = choose(suit_size + (j - i) as u64 - 1, (j - i) as u64);
+            let mut group_index = index % group_size;
+            index /= group_size;
+
+            // Undo the multiset combination, largest suit index first
+            for (k, s) in (i..j).enumerate() {
+                let n = (j - i - k) as u64;
+                if n == 1 {
+                    suit_index[s] = group_index;
+                    break;
+                }
+                let (mut low, mut high) = (0, suit_size);
+                while low + 1 < high {
+                    let mid = (low + high) / 2;
+                    if choose(mid + n - 1, n) <= group_index {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                suit_index[s] = low;
+                group_index -= choose(low + n - 1, n);
+            }
+
+            i = j;
+        }
+
+        let mut cards = vec![Card::new(Rank::ALL_VARIANTS[0], Suit::ALL_VARIANTS[0]); self.round_start[round] + self.cards_per_round[round] as usize];
+        let mut location = self.round_start.clone();
+        for (suit, index) in suit_index.iter_mut().enumerate().take(self.num_suits) {
+            let mut used = 0;
+            let mut num_used = 0;
+            for (r, location) in location.iter_mut().enumerate().take(round + 1) {
+                let n = self.count(configuration.counts[suit], r);
+                let round_size = choose((self.num_ranks - num_used) as u64, n as u64);
+                num_used += n;
+                let round_index = *index % round_size;
+                *index /= round_size;
+
+                let mut rank_set = 0;
+                for shifted_rank in rank_set_bits(index_to_rank_set(n, round_index)) {
+                    let rank = nth_unset(used, shifted_rank);
+                    rank_set |= 1 << rank;
+                    cards[*location] = Card::new(Rank::ALL_VARIANTS[self.lowest_rank + rank], Suit::ALL_VARIANTS[suit]);
+                    *location += 1;
+                }
+                used |= rank_set;
+            }
+        }
+
+        Some(cards)
+    }
+
+    fn index_next_round(&self, round: usize, cards: &[Card], state: &mut IndexState) -> u64 {
+        let mut ranks = [0u32; MAX_SUITS];
+        let mut shifted_ranks = [0u32; MAX_SUITS];
+        for card in cards {
+            let (rank, suit) = ((card.rank() as usize).wrapping_sub(self.lowest_rank), card.suit() as usize);
+            assert!(rank < self.num_ranks && suit < self.num_suits, "card outside of the deck");
+            let rank_bit = 1 << rank;
+            assert!((ranks[suit] | state.used_ranks[suit]) & rank_bit == 0, "card dealt twice");
+            ranks[suit] |= rank_bit;
+            shifted_ranks[suit] |= rank_bit >> ((rank_bit - 1) & state.used_ranks[suit]).count_ones();
+        }
+
+        for suit in 0..self.num_suits {
+            let used_size = state.used_ranks[suit].count_ones() as u64;
+            let this_size = ranks[suit].count_ones() as u64;
+            state.suit_index[suit] += state.suit_multiplier[suit] * rank_set_to_index(shifted_ranks[suit]);
+            state.suit_multiplier[suit] *= choose(self.num_ranks as u64 - used_size, this_size);
+            state.used_ranks[suit] |= ranks[suit];
+        }
+
+        let mut remaining = self.cards_per_round[round] as usize;
+        for rank_set in ranks.iter().take(self.num_suits - 1) {
+            let this_size = rank_set.count_ones() as usize;
+            state.permutation_index += state.permutation_multiplier * this_size;
+            state.permutation_multiplier *= remaining + 1;
+            remaining -= this_size;
+        }
+
+        let configuration = &self.configurations[round][self.permutation_to_configuration[round][state.permutation_index]];
+        let pi = self.permutation_to_pi[round][state.permutation_index];
+
+        let mut suit_index = [0; MAX_SUITS];
+        let mut suit_multiplier = [0; MAX_SUITS];
+        for i in 0..self.num_suits {
+            suit_index[i] = state.suit_index[pi[i]];
+            suit_multiplier[i] = state.suit_multiplier[pi[i]];
+        }
+
+        let mut index = configuration.offset;
+        let mut multiplier = 1;
+        let mut i = 0;
+        while i < self.num_suits {
+            let j = self.group_end(configuration, i);
+
+            // Suits with the same counts are interchangeable so their indices form a multiset
+            suit_index[i..j].sort();
+            let part: u64 = (i..j).enumerate()
+                .map(|(k, s)| choose(suit_index[s] + k as u64, k as u64 + 1))
+                .sum();
+            let size = choose(suit_multiplier[i] + (j - i) as u64 - 1, (j - i) as u64);
+
+            index += multiplier * part;
+            multiplier *= size;
+            i = j;
+        }
+
+        index
+    }
+
+    /// Calls observe with the packed per suit counts of every way of splitting each round's cards
+    /// over the suits. With sorted only count vectors that are non increasing over suits are
+    /// visited.
+    fn enumerate_counts<F: FnMut(usize, &[u32; MAX_SUITS])>(&self, sorted: bool, observe: &mut F) {
+        let mut used = [0; MAX_SUITS];
+        let mut counts = [0; MAX_SUITS];
+        let equal = (1 << self.num_suits) - 2;
+        self.enumerate_counts_r(sorted, 0, self.cards_per_round[0] as usize, 0, equal, &mut used, &mut counts, observe);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn enumerate_counts_r<F: FnMut(usize, &[u32; MAX_SUITS])>(&self, sorted: bool, round: usize, remaining: usize, suit: usize, equal: u32, used: &mut [usize; MAX_SUITS], counts: &mut [u32; MAX_SUITS], observe: &mut F) {
+        if suit == self.num_suits {
+            observe(round, counts);
+            if round + 1 < self.num_rounds() {
+                self.enumerate_counts_r(sorted, round + 1, self.cards_per_round[round + 1] as usize, 0, equal, used, counts, observe);
+            }
+            return;
+        }
+
+        let low = if suit == self.num_suits - 1 { remaining } else { 0 };
+        let mut high = min(self.num_ranks - used[suit], remaining);
+
+        let was_equal = sorted && equal & (1 << suit) != 0;
+        let mut previous = self.num_ranks + 1;
+        if was_equal {
+            previous = self.count(counts[suit - 1], round);
+            high = min(high, previous);
+        }
+
+        let (old_counts, old_used) = (counts[suit], used[suit]);
+        for i in low..=high {
+            let new_equal = (equal & !(1 << suit)) | (((was_equal && i == previous) as u32) << suit);
+            used[suit] = old_used + i;
+            counts[suit] = old_counts | (i as u32) << self.shift(round);
+            self.enumerate_counts_r(sorted, round, remaining - i, suit + 1, new_equal, used, counts, observe);
+        }
+        counts[suit] = old_counts;
+        used[suit] = old_used;
+    }
+
+    fn configuration(&self, round: usize, counts: [u32; MAX_SUITS], offset: u64) -> Configuration {
+        let mut suit_size = [0; MAX_SUITS];
+        let mut equal = 0;
+        for suit in 0..self.num_suits {
+            let mut size = 1;
+            let mut remaining = self.num_ranks as u64;
+            for r in 0..=round {
+                let n = self.count(counts[suit], r) as u64;
+                size *= choose(remaining, n);
+                remaining -= n;
+            }
+            suit_size[suit] = size;
+
+            if suit > 0 && counts[suit] == counts[suit - 1] {
+                equal |= 1 << suit;
+            }
+        }
+
+        Configuration { counts, suit_size, equal, offset }
+    }
+
+    fn configuration_size(&self, configuration: &Configuration) -> u64 {
+        let mut size = 1;
+        let mut i = 0;
+        while i < self.num_suits {
+            let j = self.group_end(configuration, i);
+            size *= choose(configuration.suit_size[i] + (j - i) as u64 - 1, (j - i) as u64);
+            i = j;
+        }
+        size
+    }
+
+    fn tabulate_permutation(&mut self, round: usize, counts: &[u32; MAX_SUITS]) {
+        let mut index = 0;
+        let mut multiplier = 1;
+        for r in 0..=round {
+            let mut remaining = self.cards_per_round[r] as usize;
+            for &c in counts.iter().take(self.num_suits - 1) {
+                let size = self.count(c, r);
+                index += multiplier * size;
+                multiplier *= remaining + 1;
+                remaining -= size;
+            }
+        }
+
+        // Stable sort of the suits by decreasing counts gives the canonical suit order
+        let mut pi = [0, 1, 2, 3];
+        pi[..self.num_suits].sort_by(|&a, &b| counts[b].cmp(&counts[a]));
+
+        let mut sorted_counts = [0; MAX_SUITS];
+        for i in 0..self.num_suits {
+            sorted_counts[i] = counts[pi[i]];
+        }
+
+        self.permutation_to_pi[round][index] = pi;
+        self.permutation_to_configuration[round][index] = self.configurations[round]
+            .binary_search_by(|c| c.counts.cmp(&sorted_counts))
+            .expect("every sorted count vector has a configuration");
+    }
+
+    fn num_permutations(&self, round: usize) -> usize {
+        (0..=round)
+            .map(|r| (self.cards_per_round[r] as usize + 1).pow(self.num_suits as u32 - 1))
+            .product()
+    }
+
+    /// End of the group of suits starting at i that share the same counts
+    fn group_end(&self, configuration: &Configuration, i: usize) -> usize {
+        let mut j = i + 1;
+        while j < self.num_suits && configuration.equal & (1 << j) != 0 {
+            j += 1;
+        }
+        j
+    }
+
+    fn shift(&self, round: usize) -> usize {
+        ROUND_SHIFT * (self.num_rounds() - round - 1)
+    }
+
+    /// Cards of a suit in round out of its packed counts
+    fn count(&self, counts: u32, round: usize) -> usize {
+        (counts >> self.shift(round) & ROUND_MASK) as usize
+    }
+}
+
+/// n choose k, 0 when k > n
+fn choose(n: u64, k: u64) -> u64 {
+    if k > n {
+        return 0;
+    }
+
+    let k = min(k, n - k);
+    let mut result: u128 = 1;
+    for i in 0..k {
+        result = result * (n - i) as u128 / (i + 1) as u128;
+    }
+    result as u64
+}
+
+/// Colexicographic index of a set of ranks among sets of the same size
+fn rank_set_to_index(rank_set: u32) -> u64 {
+    rank_set_bits(rank_set)
+        .enumerate()
+        .map(|(j, rank)| choose(rank as u64, j as u64 + 1))
+        .sum()
+}
+
+/// Set of n ranks with the given colexicographic index
+fn index_to_rank_set(n: usize, mut index: u64) -> u32 {
+    let mut rank_set = 0;
+    for k in (1..=n as u64).rev() {
+        let mut rank = k - 1;
+        while choose(rank + 1, k) <= index {
+            rank += 1;
+        }
+        rank_set |= 1 << rank;
+        index -= choose(rank, k);
+    }
+    rank_set
+}
+
+/// Positions of the set bits, lowest first
+fn rank_set_bits(mut rank_set: u32) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if rank_set == 0 {
+            return None;
+        }
+        let bit = rank_set.trailing_zeros() as usize;
+        rank_set &= rank_set - 1;
+        Some(bit)
+    })
+}
+
+/// Position of the nth unset bit of used
+fn nth_unset(used: u32, n: usize) -> usize {
+    (0..32).filter(|&b| used & (1 << b) == 0).nth(n).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::game::generate_deck;
+
+    use itertools::Itertools;
+
+    #[test]
+    fn holdem_round_sizes() {
+        let indexer = HandIndexer::new(4, 13, 0, &[2, 3]);
+        assert_eq!(indexer.round_size(0), 169);
+        assert_eq!(indexer.round_size(1), 1_286_792);
+    }
+
+    #[test]
+    fn leduc_round_trip() {
+        let indexer = HandIndexer::new(2, 3, 0, &[1, 1]);
+        for round in 0..indexer.num_rounds() {
+            for i in 0..indexer.round_size(round) {
+                let cards = indexer.unindex(round, i).unwrap();
+                assert_eq!(indexer.index(&cards), i);
+            }
+            assert!(indexer.unindex(round, indexer.round_size(round)).is_none());
+        }
+    }
+
+    #[test]
+    fn leduc_hands_index_densely() {
+        let indexer = HandIndexer::new(2, 3, 0, &[1, 1]);
+        let indices: Vec<u64> = generate_deck(2, 3, 0).permutations(2)
+            .map(|cards| indexer.index(&cards))
+            .sorted()
+            .dedup()
+            .collect();
+        assert_eq!(indices, (0..indexer.round_size(1)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn suit_permutations_share_an_index() {
+        let indexer = HandIndexer::new(4, 13, 0, &[2, 3]);
+        let hand: Vec<Card> = ["Ah", "Kh", "Qh", "7s", "2c"].iter().map(|c| c.parse().unwrap()).collect();
+        let permuted: Vec<Card> = ["Ad", "Kd", "Qd", "7c", "2s"].iter().map(|c| c.parse().unwrap()).collect();
+        assert_eq!(indexer.index(&hand), indexer.index(&permuted));
+        assert_eq!(indexer.index(&hand[..2]), indexer.index(&permuted[..2]));
+    }
+
+    #[test]
+    fn holdem_flop_round_trip_sample() {
+        let indexer = HandIndexer::new(4, 13, 0, &[2, 3]);
+        for i in (0..indexer.round_size(1)).step_by(997) {
+            let cards = indexer.unindex(1, i).unwrap();
+            assert_eq!(cards.len(), 5);
+            assert_eq!(indexer.index(&cards), i);
+        }
+    }
+
+    #[test]
+    fn short_deck_round_trip() {
+        // Sixes to aces, 9 pairs and 36 suited and offsuit hands each
+        let indexer = HandIndexer::new(4, 9, 4, &[2]);
+        assert_eq!(indexer.round_size(0), 81);
+        for i in 0..indexer.round_size(0) {
+            let cards = indexer.unindex(0, i).unwrap();
+            assert!(cards.iter().all(|c| c.rank() >= Rank::Six));
+            assert_eq!(indexer.index(&cards), i);
+        }
+        let hands: Vec<u64> = generate_deck(4, 9, 4).permutations(2)
+            .map(|cards| indexer.index(&cards))
+            .sorted()
+            .dedup()
+            .collect();
+        assert_eq!(hands.len(), 81);
+    }
+}
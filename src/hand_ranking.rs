@@ -0,0 +1,230 @@
+/*
+* Showdown hand rankings. Hands are ranked on their best five cards with every kicker taken into
+* account, hands with fewer cards are ranked on what they have, so Kuhn and Leduc hands work too.
+*/
+
+use poker::Card;
+
+use serde::{Deserialize, Serialize};
+
+/// Strength of a hand, higher beats lower and equal values split
+pub type HandValue = u32;
+
+/// Ranks the cards a player can use at showdown, hole cards and board together. lowest_rank is
+/// the lowest rank in the deck, aces play low right below it in straights.
+pub trait HandRanker: Send + Sync {
+    fn rank(&self, cards: &[Card], lowest_rank: u8) -> HandValue;
+}
+
+/// Hand ranking a game is played with
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum HandRanking {
+    #[default]
+    Standard,
+    /// Only pairs, two pairs, trips and so on count, there are no straights or flushes
+    RankOnly,
+    /// Flushes beat full houses
+    ShortDeck,
+}
+
+impl HandRanking {
+    pub fn ranker(self) -> &'static dyn HandRanker {
+        match self {
+            HandRanking::Standard => &StandardRanker,
+            HandRanking::RankOnly => &RankOnlyRanker,
+            HandRanking::ShortDeck => &ShortDeckRanker,
+        }
+    }
+}
+
+/// Usual poker hand ranking, aces also play low in a five high straight
+pub struct StandardRanker;
+
+impl HandRanker for StandardRanker {
+    fn rank(&self, cards: &[Card], lowest_rank: u8) -> HandValue {
+        evaluate(cards, lowest_rank, true, false)
+    }
+}
+
+/// Ranks hands only by how many cards of each rank they hold, then by rank, like in Kuhn and
+/// Leduc poker
+pub struct RankOnlyRanker;
+
+impl HandRanker for RankOnlyRanker {
+    fn rank(&self, cards: &[Card], lowest_rank: u8) -> HandValue {
+        evaluate(cards, lowest_rank, false, false)
+    }
+}
+
+/// Short deck ranking, a flush beats a full house
+pub struct ShortDeckRanker;
+
+impl HandRanker for ShortDeckRanker {
+    fn rank(&self, cards: &[Card], lowest_rank: u8) -> HandValue {
+        evaluate(cards, lowest_rank, true, true)
+    }
+}
+
+const NUM_RANKS: usize = 13;
+const ACE: usize = 12;
+
+/// Hand categories from worst to best, full house and flush swap places in short deck
+const HIGH_CARD: u32 = 0;
+const PAIR: u32 = 1;
+const TWO_PAIR: u32 = 2;
+const TRIPS: u32 = 3;
+const STRAIGHT: u32 = 4;
+const FLUSH: u32 = 5;
+const FULL_HOUSE: u32 = 6;
+const QUADS: u32 = 7;
+const STRAIGHT_FLUSH: u32 = 8;
+
+/// Category in the top bits followed by up to five ranks, most important first
+fn value(category: u32, ranks: &[usize]) -> HandValue {
+    let mut v = category;
+    for i in 0..5 {
+        v = (v << 4) | ranks.get(i).map_or(0, |&r| r as u32 + 1);
+    }
+    v
+}
+
+/// Ranks set in mask from highest to lowest, skipping the excluded ones
+fn top_ranks(mask: u16, exclude: &[usize], n: usize) -> Vec<usize> {
+    (0..NUM_RANKS).rev()
+        .filter(|&r| mask & (1 << r) != 0 && !exclude.contains(&r))
+        .take(n)
+        .collect()
+}
+
+/// High rank of the best straight in mask, an ace can also sit below lowest_rank
+fn straight_high(mask: u16, lowest_rank: u8) -> Option<usize> {
+    // Bit r + 1 is rank r and the low ace takes the bit of the rank under the deck
+    let extended = ((mask as u32) << 1) | (((mask as u32 >> ACE) & 1) << lowest_rank);
+    (4..=NUM_RANKS).rev()
+        .find(|&top| (extended >> (top - 4)) & 0x1f == 0x1f)
+        .map(|top| top - 1)
+}
+
+fn evaluate(cards: &[Card], lowest_rank: u8, straights_and_flushes: bool, flush_beats_full_house: bool) -> HandValue {
+    let mut counts = [0u8; NUM_RANKS];
+    let mut suit_masks = [0u16; 4];
+    for c in cards {
+        counts[c.rank() as usize] += 1;
+        suit_masks[c.suit() as usize] |= 1 << c.rank() as usize;
+    }
+    let rank_mask = (0..NUM_RANKS).filter(|&r| counts[r] > 0).fold(0u16, |m, r| m | (1 << r));
+    let with_count = |n: u8| -> Vec<usize> { (0..NUM_RANKS).rev().filter(|&r| counts[r] >= n).collect() };
+
+    let flush_masks: Vec<u16> = if straights_and_flushes {
+        suit_masks.iter().copied().filter(|m| m.count_ones() >= 5).collect()
+    } else {
+        Vec::new()
+    };
+
+    if let Some(high) = flush_masks.iter().filter_map(|&m| straight_high(m, lowest_rank)).max() {
+        return value(STRAIGHT_FLUSH, &[high]);
+    }
+
+    if let Some(&quads) = with_count(4).first() {
+        return value(QUADS, &[&[quads][..], &top_ranks(rank_mask, &[quads], 1)].concat());
+    }
+
+    let (full_house_category, flush_category) = if flush_beats_full_house { (FLUSH, FULL_HOUSE) } else { (FULL_HOUSE, FLUSH) };
+    let trips = with_count(3);
+    let full_house = trips.first().and_then(|&t| {
+        with_count(2).into_iter().find(|&p| p != t).map(|p| value(full_house_category, &[t, p]))
+    });
+    let flush = flush_masks.iter()
+        .map(|&m| value(flush_category, &top_ranks(m, &[], 5)))
+        .max();
+    if let Some(v) = full_house.max(flush) {
+        return v;
+    }
+
+    if straights_and_flushes {
+        if let Some(high) = straight_high(rank_mask, lowest_rank) {
+            return value(STRAIGHT, &[high]);
+        }
+    }
+
+    if let Some(&t) = trips.first() {
+        return value(TRIPS, &[&[t][..], &top_ranks(rank_mask, &[t], 2)].concat());
+    }
+
+    let pairs = with_count(2);
+    match pairs[..] {
+        [high, low, ..] => value(TWO_PAIR, &[&[high, low][..], &top_ranks(rank_mask, &[high, low], 1)].concat()),
+        [pair] => value(PAIR, &[&[pair][..], &top_ranks(rank_mask, &[pair], 3)].concat()),
+        [] => value(HIGH_CARD, &top_ranks(rank_mask, &[], 5)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::acpc::parse_cards;
+
+    fn rank(ranking: HandRanking, lowest_rank: u8, cards: &str) -> HandValue {
+        ranking.ranker().rank(&parse_cards(cards).unwrap(), lowest_rank)
+    }
+
+    fn standard(cards: &str) -> HandValue {
+        rank(HandRanking::Standard, 0, cards)
+    }
+
+    #[test]
+    fn kickers_break_ties() {
+        // Flushes are compared card by card down to the fifth
+        assert!(standard("AhJh9h6h3h2c") > standard("AhJh9h6h2h3c"));
+        assert!(standard("AhKh9h6h3hQs") > standard("AhQhJhTh8hKs"));
+        assert!(standard("AsAdKc7h2d") > standard("AsAdQcJh9d"));
+        assert!(standard("AsAdKc8h2d") > standard("AsAdKc7h6d"));
+        assert!(standard("KsKd5c5h9d") > standard("KsKd5c5h8d"));
+        assert!(standard("AsKd9c7h3d") > standard("AsKd9c7h2d"));
+        // Cards past the best five don't count
+        assert_eq!(standard("AsAdKcQhJd3c"), standard("AsAdKcQhJd2c"));
+        assert_eq!(standard("AhJh9h6h3h"), standard("AsJs9s6s3s"));
+    }
+
+    #[test]
+    fn standard_categories_and_the_wheel() {
+        let hands = ["AsKd9c7h3d", "2s2d9c7h3d", "2s2d3c3h4d", "2s2d2c3h4d", "As2d3c4h5d", "2s3d4c5h6d", "2h5h7h9hJh", "2s2d2c3h3d", "2s2d2c2h3d", "As2s3s4s5s", "9sTsJsQsKs"];
+        for pair in hands.windows(2) {
+            assert!(standard(pair[0]) < standard(pair[1]), "{} should lose to {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn rank_only_ranks_kuhn_and_leduc_hands() {
+        let rank_only = |cards| rank(HandRanking::RankOnly, 0, cards);
+        // Kuhn, one card each
+        assert!(rank_only("4c") > rank_only("3c"));
+        assert!(rank_only("3c") > rank_only("2c"));
+        // Leduc, a pair with the board beats any high card
+        assert!(rank_only("2c2h") > rank_only("4c3h"));
+        assert!(rank_only("4c3h") > rank_only("4h2c"));
+        assert_eq!(rank_only("4c3h"), rank_only("4h3c"));
+        // No straights or flushes
+        assert!(rank_only("2s2d9c7h3d") > rank_only("6h7h8h9hTh"));
+    }
+
+    #[test]
+    fn short_deck_flush_beats_full_house() {
+        let short_deck = |cards| rank(HandRanking::ShortDeck, 4, cards);
+        assert!(short_deck("6h8hTh9hAh") > short_deck("AsAdAcKhKd"));
+        assert!(short_deck("AsAdAcAhKd") > short_deck("6h8hTh9hAh"));
+        assert!(standard("AsAdAcKhKd") > standard("6h8hTh9hAh"));
+    }
+
+    #[test]
+    fn short_deck_ace_plays_below_the_six() {
+        let short_deck = |cards| rank(HandRanking::ShortDeck, 4, cards);
+        let low_straight = short_deck("As6d7c8h9d");
+        assert!(low_straight > short_deck("AsAd7c8hKd"));
+        assert!(low_straight < short_deck("6s7d8c9hTd"));
+        assert!(short_deck("As6s7s8s9s") > short_deck("AsAdAcAhKd"));
+        // In a full deck the same cards are ace high
+        assert!(standard("As6d7c8h9d") < standard("2s2d7c8h9d"));
+    }
+}
@@ -4,9 +4,20 @@ extern crate log;
 pub mod game;
 
 pub mod abstract_game;
+pub mod accumulator;
+pub mod acpc;
 pub mod action_abstraction;
+pub mod action_translation;
+pub mod best_response;
+pub mod bucket_table;
 pub mod card_abstraction;
 pub mod cfr;
+pub mod client;
+pub mod clustering;
+pub mod dealer;
+pub mod equity;
+pub mod hand_indexer;
+pub mod hand_ranking;
 pub mod node;
 pub mod play;
 pub mod strategy;
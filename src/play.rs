@@ -1,15 +1,19 @@
 use super::{
     abstract_game::AbstractGame,
+    action_translation::{abstract_to_real, same_decision, translate_raise, TranslationMethod},
     strategy::Strategy,
-    game::Action,
+    game::{Action, GameState},
 };
 
-use poker::Evaluator;
+use rand::Rng;
 
 use std::io;
 
-pub fn play(abstract_game: &mut AbstractGame, strategy: Strategy) {
-    let evaluator = Evaluator::new();
+/// Plays against the strategy on stdin. Raises the action abstraction doesn't contain are mapped
+/// onto abstract raises with translation, the real game state is kept apart from the abstract
+/// node for legality and payouts. Cards, translation and the bot's actions all come from rng.
+/// In games with a moving button the player changes position every hand.
+pub fn play<R: Rng>(abstract_game: &AbstractGame, strategy: Strategy, translation: TranslationMethod, rng: &mut R) {
     let player: u8 = 0;
     let mut total_payouts = vec![0; abstract_game.game_info.num_players() as usize];
     let mut matches = 0;
@@ -19,7 +23,7 @@ pub fn play(abstract_game: &mut AbstractGame, strategy: Strategy) {
         let mut action_line: String = String::new();
         match io::stdin().read_line(&mut action_line) {
             Ok(_) => (),
-            Err(e) => continue
+            Err(_) => continue
         };
         match action_line.trim() {
             "y" => (),
@@ -28,30 +32,40 @@ pub fn play(abstract_game: &mut AbstractGame, strategy: Strategy) {
             _ => break
         };
 
-        let (hole_cards, board_cards) = abstract_game.game_info.deal_hole_cards_and_board_cards();
-        let mut board_cards_i = abstract_game.game_info.total_board_cards(0) as usize;
+        let mut node_id = abstract_game.get_root_node_id();
+        let mut state = GameState::new(&abstract_game.game_info, matches).with_cards();
+        // Once an off tree action desyncs the abstract game from the real one the bot just calls
+        let mut in_tree = true;
 
-        let mut node_id = abstract_game.nodes.get_root_node_id();
-        let mut current_node = abstract_game.nodes.get_node(node_id).unwrap();
-        let mut state = current_node.state.clone();
-        
-        while !state.is_finished() {
+        loop {
+            while let Some(card) = state.sample_chance(&abstract_game.game_info, rng) {
+                state = state.apply_chance(&abstract_game.game_info, card).unwrap();
+            }
+            if state.is_finished() {
+                break;
+            }
+
+            println!("Button: Player {}", state.button());
             println!("Chips in pot: {}", state.pot_total(&abstract_game.game_info));
             for i in 0..abstract_game.game_info.num_players() {
                 println!("Player {} chips: {}", i, state.player_stack(i) - state.player_spent(i));
             }
             print!("Player hole cards: ");
-            for card in &hole_cards[player as usize] {
+            for card in state.hole_cards(player) {
                 print!("{} ", card);
             }
-            print!("\n");
+            println!();
             print!("Board cards: ");
-            for i in 0..board_cards_i {
-                print!("{} ", board_cards[i]);
+            for card in state.board_cards() {
+                print!("{} ", card);
             }
-            print!("\n");
+            println!();
+
+            let abstract_state = abstract_game.get_state(node_id);
+            in_tree = in_tree && same_decision(&abstract_game.game_info, &state, abstract_state);
 
             let mut action;
+            let mut abstract_action = None;
             if state.current_player().unwrap() == player {
                 loop {
                     println!("Input action:");
@@ -62,42 +76,60 @@ pub fn play(abstract_game: &mut AbstractGame, strategy: Strategy) {
 
                     if state.is_valid_action(&abstract_game.game_info, action) {
                         break;
-                    } else {
-                        println!("Not a valid action");
                     }
+                    println!("Not a valid action");
                 }
+
+                if in_tree {
+                    abstract_action = match action {
+                        Action::Raise(r) if abstract_game.get_child(node_id, action).is_none() => {
+                            translate_raise(translation, &abstract_game.game_info, &state, r, abstract_state, &abstract_game.get_actions(abstract_state), rng)
+                        },
+                        _ => Some(action),
+                    };
+                    if let Some(Action::Raise(r)) = abstract_action.filter(|&a| a != action) {
+                        println!("Raise translated to abstract raise {}", r);
+                    }
+                }
+            } else if in_tree {
+                let bucket_id = abstract_game.get_bucket(abstract_state.current_round(), state.board_cards(), state.hole_cards(state.current_player().unwrap()));
+                let a = strategy.sample(abstract_game, node_id, bucket_id, rng);
+                abstract_action = Some(a);
+                action = abstract_to_real(&abstract_game.game_info, &state, abstract_state, a);
             } else {
-                let bucket_id = abstract_game.get_bucket(state.current_round(), &board_cards, &hole_cards[state.current_player().unwrap() as usize]);
-                action = strategy.sample(&abstract_game, node_id, bucket_id);
+                action = Action::Call;
             }
 
             println!("Player {}: {}\n", state.current_player().unwrap(), action);
             state = state.apply_action_no_cards(&abstract_game.game_info, action).unwrap();
-            node_id = abstract_game.apply_action_to_node(node_id, &mut board_cards_i, action);
-            current_node = abstract_game.nodes.get_node(node_id).unwrap();
+            match abstract_action.and_then(|a| abstract_game.get_child(node_id, a)) {
+                Some(child) => node_id = child,
+                None => in_tree = false,
+            }
         }
 
         println!("Round finished");
 
+        let payouts = state.get_dealt_payouts(&abstract_game.game_info).unwrap();
         for i in 0..abstract_game.game_info.num_players() {
-            let payout = state.get_payout(&abstract_game.game_info, &evaluator, &board_cards, &hole_cards, i);
+            let payout = payouts[i as usize];
             total_payouts[i as usize] += payout;
             print!("Player {} hole cards: ", i);
-            for card in &hole_cards[i as usize] {
+            for card in state.hole_cards(i) {
                 print!("{} ", card);
             }
-            print!("\n");
+            println!();
             println!("Player {} payout: {}", i, payout);
         }
 
         matches += 1;
 
-        print!("\n");
+        println!();
         println!("Total payouts after {} matches", matches);
         for i in 0..abstract_game.game_info.num_players() {
             println!("Player {} total payout: {}", i, total_payouts[i as usize]);
         }
-        print!("\n");
+        println!();
     }
 }
 
@@ -105,9 +137,9 @@ fn get_player_action() -> Result<Action, io::Error> {
     let mut action_line: String = String::new();
     io::stdin().read_line(&mut action_line)?;
 
-    let action_line: Vec<&str> = action_line.trim().split_whitespace().collect();
+    let action_line: Vec<&str> = action_line.split_whitespace().collect();
 
-    if action_line.len() < 1 {
+    if action_line.is_empty() {
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid action input"));
     }
 
@@ -2,6 +2,7 @@
 
 use super::{
     abstract_game::AbstractGame,
+    accumulator::Accumulator,
     card_abstraction::BucketId,
     game::Action,
     node::NodeId,
@@ -9,15 +10,26 @@ use super::{
 
 use serde::{Serialize, Deserialize};
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 use std::fs::File;
 use std::io::BufReader;
+use std::sync::Mutex;
 
 use rand::prelude::*;
 
+/// Average strategy weights per infoset in the order `ActionAbstraction::get_actions` returns the
+/// actions, they don't have to be normalized
 #[derive(Debug, Deserialize, Serialize)]
-pub struct Strategy(pub BTreeMap<(NodeId, BucketId), BTreeMap<Action, i32>>);
+pub struct Strategy(pub Regrets<f64>);
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::new()
+    }
+}
 
 impl Strategy {
     pub fn new() -> Strategy {
@@ -29,25 +41,260 @@ impl Strategy {
         bincode::deserialize_from(&mut r).unwrap()
     }
 
-    pub fn sample(&self, abstract_game: &AbstractGame, node_id: NodeId, bucket_id: BucketId) -> Action {
-        let mut rng = rand::thread_rng();
-        let sigma = match self.0.get(&(node_id, bucket_id)) {
-            Some(s) => s.clone(),
-            None => {
-                let mut action_map: BTreeMap<Action, i32> = BTreeMap::new();
+    /// Builds a strategy out of accumulated weights of any accumulator type
+    pub fn from_weights<T: Accumulator>(weights: Regrets<T>) -> Strategy {
+        Strategy(weights.into_iter()
+            .map(|(k, v)| (k, v.into_iter().map(|w| w.to_f64()).collect()))
+            .collect())
+    }
 
-                let current_node = abstract_game.nodes.get_node(node_id).unwrap();
-                for a in abstract_game.get_actions(&current_node.state) {
-                    action_map.insert(a, 0); // inserts with uniform distribution
-                }
-                action_map
-            },
+    /// Normalized probability of each of actions, uniform if the infoset was never visited
+    pub fn probabilities(&self, node_id: NodeId, bucket_id: BucketId, actions: &[Action]) -> Vec<f32> {
+        let weights: Vec<f64> = match self.0.get(&(node_id, bucket_id)) {
+            Some(s) => (0..actions.len()).map(|a| s.get(a).unwrap_or(&0.).max(0.)).collect(),
+            None => vec![0.; actions.len()],
         };
-        println!("({}, {}): {:?}", node_id, bucket_id, sigma);
 
-        *sigma.iter().collect::<Vec<(&Action, &i32)>>().choose_weighted(&mut rng, |item| item.1).unwrap().0
+        let sum: f64 = weights.iter().sum();
+        if sum > 0. {
+            weights.iter().map(|&w| (w / sum) as f32).collect()
+        } else {
+            vec![1. / actions.len() as f32; actions.len()]
+        }
+    }
+
+    pub fn sample<R: Rng>(&self, abstract_game: &AbstractGame, node_id: NodeId, bucket_id: BucketId, rng: &mut R) -> Action {
+        let actions = abstract_game.get_actions(abstract_game.get_state(node_id));
+        let sigma = self.probabilities(node_id, bucket_id, &actions);
+        debug!("({}, {}): {:?}", node_id, bucket_id, actions.iter().zip(&sigma).collect::<Vec<_>>());
+
+        *actions.iter().zip(&sigma).collect::<Vec<_>>().choose_weighted(rng, |item| *item.1).unwrap().0
+    }
+}
+
+
+/// Values of every infoset, indexed by action in the order `ActionAbstraction::get_actions` returns them
+pub type Regrets<T = f64> = BTreeMap<(NodeId, BucketId), Vec<T>>;
+
+
+/// Flat storage for one shard, every infoset owns a contiguous slice of values
+struct Arena<T> {
+    /// Offset and number of actions of each infoset's slice
+    index: HashMap<(NodeId, BucketId), (usize, usize)>,
+    values: Vec<T>,
+}
+
+impl<T: Accumulator> Arena<T> {
+    fn new() -> Arena<T> {
+        Arena {
+            index: HashMap::new(),
+            values: Vec::new(),
+        }
     }
+
+    /// Slice for key, zeroed with num_actions values if it isn't there yet
+    fn get_or_insert(&mut self, key: (NodeId, BucketId), num_actions: usize) -> &mut [T] {
+        let values = &mut self.values;
+        let (offset, len) = *self.index.entry(key).or_insert_with(|| {
+            let offset = values.len();
+            values.resize(offset + num_actions, T::default());
+            (offset, num_actions)
+        });
+        &mut self.values[offset..offset + len]
+    }
+}
+
+/// Regrets or strategy weights that can be shared between training threads. Infosets are split
+/// over shards with their own locks so threads only contend when they hit the same shard.
+pub struct InfosetTable<T: Accumulator> {
+    shards: Vec<Mutex<Arena<T>>>,
 }
 
+impl<T: Accumulator> InfosetTable<T> {
+    const NUM_SHARDS: usize = 64;
+
+    pub fn new() -> InfosetTable<T> {
+        InfosetTable {
+            shards: (0..Self::NUM_SHARDS).map(|_| Mutex::new(Arena::new())).collect(),
+        }
+    }
+
+    pub fn from_map(map: Regrets<T>) -> InfosetTable<T> {
+        let table = InfosetTable::new();
+        for (key, values) in map {
+            table.shards[Self::shard(&key)].lock().unwrap()
+                .get_or_insert(key, values.len())
+                .copy_from_slice(&values);
+        }
+
+        table
+    }
+
+    /// Copies the table out into one map, used for saving
+    pub fn to_map(&self) -> Regrets<T> {
+        let mut map = BTreeMap::new();
+        for shard in &self.shards {
+            let arena = shard.lock().unwrap();
+            map.extend(arena.index.iter().map(|(k, &(offset, len))| (*k, arena.values[offset..offset + len].to_vec())));
+        }
+        map
+    }
+
+    /// Number of infosets stored
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().index.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-pub type Regrets = BTreeMap<(NodeId, BucketId), BTreeMap<Action, i32>>;
+    fn shard(key: &(NodeId, BucketId)) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as usize % Self::NUM_SHARDS
+    }
+
+    /// Applies f to the values for key without copying them out, inserting num_actions zeros
+    /// first if it isn't there yet
+    pub fn read<R, F: FnOnce(&[T]) -> R>(&self, key: (NodeId, BucketId), num_actions: usize, f: F) -> R {
+        f(self.shards[Self::shard(&key)].lock().unwrap().get_or_insert(key, num_actions))
+    }
+
+    /// Applies f to the values for key, inserting num_actions zeros first if it isn't there yet
+    pub fn update<F: FnOnce(&mut [T])>(&self, key: (NodeId, BucketId), num_actions: usize, f: F) {
+        f(self.shards[Self::shard(&key)].lock().unwrap().get_or_insert(key, num_actions));
+    }
+
+    /// Applies f to every stored value
+    pub fn for_each_value_mut<F: FnMut(&mut T)>(&self, mut f: F) {
+        for shard in &self.shards {
+            shard.lock().unwrap().values.iter_mut().for_each(&mut f);
+        }
+    }
+}
+
+impl<T: Accumulator> Default for InfosetTable<T> {
+    fn default() -> Self {
+        InfosetTable::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        action_abstraction::ActionAbstraction,
+        card_abstraction::CardAbstraction,
+        game::{GameInfo, GameState},
+    };
+
+    use std::path::Path;
+    use std::thread;
+
+    fn leduc() -> AbstractGame {
+        let game_info = GameInfo::load_game_info(Path::new("game_configs/leduc.json"));
+        let state = GameState::new(&game_info, 0);
+        let action_abstraction = ActionAbstraction::from_config(Path::new("game_configs/leduc_action_abstraction.json"));
+        let card_abstraction = CardAbstraction::from_config(Path::new("game_configs/leduc_card_abstraction.json"));
+        AbstractGame::new(game_info, state, action_abstraction, card_abstraction)
+    }
+
+    /// Value standing in for action so the slot it ends up in can be checked
+    fn action_value(action: Action) -> f64 {
+        match action {
+            Action::Fold => 1.,
+            Action::Call => 2.,
+            Action::Raise(r) => 100. + r as f64,
+        }
+    }
+
+    #[test]
+    fn maps_round_trip() {
+        let mut map: Regrets<i32> = BTreeMap::new();
+        for node_id in 0..100 {
+            // Slices of different lengths so offsets in the arenas don't line up
+            let num_actions = 1 + node_id % 4;
+            for bucket_id in 0..3 {
+                let values = (0..num_actions).map(|a| (node_id * 1000 + bucket_id as usize * 10 + a) as i32 - 5000).collect();
+                map.insert((node_id, bucket_id), values);
+            }
+        }
+
+        let table = InfosetTable::from_map(map.clone());
+        assert_eq!(table.len(), map.len());
+        assert_eq!(table.to_map(), map);
+        assert_eq!(InfosetTable::from_map(table.to_map()).to_map(), map);
+
+        // New infosets go after the loaded ones without touching them
+        table.update((1000, 0), 2, |v| v.copy_from_slice(&[7, 8]));
+        assert_eq!(table.read((42, 2), 3, |v| v.to_vec()), map[&(42, 2)]);
+        assert_eq!(table.read((1000, 0), 2, |v| v.to_vec()), vec![7, 8]);
+    }
+
+    #[test]
+    fn values_stay_in_action_order() {
+        let abstract_game = leduc();
+        let table: InfosetTable<f64> = InfosetTable::new();
+        for (&node_id, node) in &abstract_game.nodes.nodes_map {
+            if node.state.is_finished() {
+                continue;
+            }
+            let actions = abstract_game.get_actions(&node.state);
+            for bucket_id in 0..6 {
+                table.update((node_id, bucket_id), actions.len(), |v| {
+                    for (a, &action) in actions.iter().enumerate() {
+                        v[a] = action_value(action);
+                    }
+                });
+            }
+        }
+
+        let strategy = Strategy::from_weights(table.to_map());
+        for (&node_id, node) in &abstract_game.nodes.nodes_map {
+            if node.state.is_finished() {
+                continue;
+            }
+            let actions = abstract_game.get_actions(&node.state);
+            let expected: Vec<f64> = actions.iter().map(|&a| action_value(a)).collect();
+            for bucket_id in 0..6 {
+                assert_eq!(table.read((node_id, bucket_id), actions.len(), |v| v.to_vec()), expected);
+                assert_eq!(strategy.0[&(node_id, bucket_id)], expected);
+            }
+        }
+    }
+
+    #[test]
+    fn concurrent_updates_all_land() {
+        let table: InfosetTable<i32> = InfosetTable::new();
+        let num_threads = 8;
+        let num_infosets = 200;
+        thread::scope(|s| {
+            for thread_id in 0..num_threads {
+                let table = &table;
+                s.spawn(move || {
+                    for round in 0..50 {
+                        for infoset in 0..num_infosets {
+                            // Each thread goes through the infosets in a different order so they
+                            // keep hitting the same shards
+                            let node_id = (infoset + thread_id * 37 + round) % num_infosets;
+                            table.update((node_id, 1), 3, |v| {
+                                v[0] += 1;
+                                v[thread_id % 3] += 2;
+                            });
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(table.len(), num_infosets);
+        let map = table.to_map();
+        for node_id in 0..num_infosets {
+            // 8 threads add 1 to the first action 50 times, threads 0, 3 and 6 add 2 more to it
+            // and the rest add 2 to the other actions
+            assert_eq!(map[&(node_id, 1)], vec![400 + 3 * 100, 3 * 100, 2 * 100]);
+        }
+    }
+}